@@ -0,0 +1,192 @@
+//! APCA (Accessible Perceptual Contrast Algorithm) lightness contrast, and
+//! the font lookup table that turns a text size and weight into the Lc it
+//! needs. Constants follow APCA-W3 0.0.98G-4g and the 0.1.9 lookup table.
+
+use std::{fmt::Display, str::FromStr};
+
+use crate::color::{srgb_appearance, Color, ColorSpace};
+use crate::cost::ScaledCost;
+use crate::error::{Error, Result};
+
+/// Screen luminance as APCA estimates it: a plain 2.4 power curve, with
+/// near-black values soft-clamped up to account for flare.
+fn screen_luminance(c: Color, space: ColorSpace) -> f32 {
+    let c = srgb_appearance(c, space);
+    let y = 0.2126729 * c.red.max(0.).powf(2.4)
+        + 0.7151522 * c.green.max(0.).powf(2.4)
+        + 0.0721750 * c.blue.max(0.).powf(2.4);
+    const BLACK_THRESHOLD: f32 = 0.022;
+    if y < BLACK_THRESHOLD {
+        y + (BLACK_THRESHOLD - y).powf(1.414)
+    } else {
+        y
+    }
+}
+
+/// Lc of `text` on `bg`, roughly in [-108, 106]. Positive for dark text
+/// on a light background, negative for light text on a dark one; only the
+/// magnitude matters for legibility.
+pub fn lightness_contrast(text: Color, bg: Color, space: ColorSpace) -> f32 {
+    let (y_text, y_bg) = (screen_luminance(text, space), screen_luminance(bg, space));
+    if (y_bg - y_text).abs() < 0.0005 {
+        return 0.;
+    }
+    const SCALE: f32 = 1.14;
+    const OFFSET: f32 = 0.027;
+    const LOW_CLIP: f32 = 0.1;
+    let lc = if y_bg > y_text {
+        let sapc = (y_bg.powf(0.56) - y_text.powf(0.57)) * SCALE;
+        if sapc < LOW_CLIP {
+            0.
+        } else {
+            sapc - OFFSET
+        }
+    } else {
+        let sapc = (y_bg.powf(0.65) - y_text.powf(0.62)) * SCALE;
+        if sapc > -LOW_CLIP {
+            0.
+        } else {
+            sapc + OFFSET
+        }
+    };
+    lc * 100.
+}
+
+/// Font weights that head the columns of [`FONT_LOOKUP`].
+const WEIGHTS: [u16; 9] = [100, 200, 300, 400, 500, 600, 700, 800, 900];
+
+/// Marks sizes that no text may use at that Lc, only non-text elements.
+const NON_TEXT: f32 = 777.;
+
+/// Minimum font size in px for each weight in [`WEIGHTS`], by ascending
+/// Lc. Sizes of at least [`NON_TEXT`] mean the Lc is too low for text.
+#[rustfmt::skip]
+const FONT_LOOKUP: [(f32, [f32; 9]); 23] = [
+    (15., [777., 777., 777., 777., 777., 777., 777., 777., 777.]),
+    (20., [777., 777., 777., 777., 777., 777., 777., 777., 777.]),
+    (25., [777., 777., 777., 120., 120., 108., 96., 96., 96.]),
+    (30., [777., 777., 120., 108., 108., 96., 72., 72., 72.]),
+    (35., [777., 120., 108., 96., 72., 60., 48., 48., 48.]),
+    (40., [120., 108., 96., 60., 48., 42., 32., 32., 32.]),
+    (45., [108., 96., 72., 42., 32., 28., 24., 24., 24.]),
+    (50., [96., 72., 60., 32., 28., 24., 21., 21., 21.]),
+    (55., [80., 60., 48., 28., 24., 21., 18., 18., 18.]),
+    (60., [72., 48., 42., 24., 21., 18., 16., 16., 18.]),
+    (65., [68., 46., 32., 21.75, 19., 17., 15., 16., 18.]),
+    (70., [64., 44., 28., 19.5, 18., 16., 14.5, 16., 18.]),
+    (75., [60., 42., 24., 18., 16., 15., 14., 16., 18.]),
+    (80., [56., 38.25, 23., 17.25, 15.81, 14.81, 14., 16., 18.]),
+    (85., [52., 34.5, 22., 16.5, 15.625, 14.625, 14., 16., 18.]),
+    (90., [48., 32., 21., 16., 15., 14., 14., 16., 18.]),
+    (95., [45., 28., 19.5, 15.5, 14.5, 14., 13.5, 16., 18.]),
+    (100., [42., 26.5, 18.5, 15., 14., 13.5, 13., 16., 18.]),
+    (105., [39., 25., 18., 14.5, 14., 13., 12., 16., 18.]),
+    (110., [36., 24., 18., 14., 13., 12., 11., 16., 18.]),
+    (115., [34.5, 22.5, 17.25, 12.5, 11.875, 11.25, 10.625, 14.5, 16.5]),
+    (120., [33., 21., 16.5, 11., 11., 11., 10., 13., 15.]),
+    (125., [32., 20., 16., 10., 10., 10., 10., 12., 14.]),
+];
+
+/// What a foreground color is used for, which sets the Lc it needs.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TextUse {
+    pub size_px: f32,
+    pub weight: u16,
+}
+
+impl TextUse {
+    /// The lowest Lc at which text of this size and weight is readable.
+    /// Weights between table columns use the lighter column, and sizes too
+    /// small for any listed Lc need the table's maximum.
+    pub fn required_lc(self) -> f32 {
+        let column = WEIGHTS.iter().rposition(|&w| w <= self.weight).unwrap_or(0);
+        FONT_LOOKUP
+            .iter()
+            .find(|(_, sizes)| sizes[column] < NON_TEXT && sizes[column] <= self.size_px)
+            .map_or(FONT_LOOKUP[FONT_LOOKUP.len() - 1].0, |&(lc, _)| lc)
+    }
+
+    /// 100 when `text` on `bg` is below the required Lc, and decaying from
+    /// 50 towards 0 as the margin above it grows.
+    pub fn cost(self, text: Color, bg: Color, space: ColorSpace) -> ScaledCost {
+        let margin = lightness_contrast(text, bg, space).abs() - self.required_lc();
+        if margin < 0. {
+            return ScaledCost::new(100.);
+        }
+        ScaledCost::new(100. / (1. + (0.2 * margin).exp()))
+    }
+}
+
+impl FromStr for TextUse {
+    type Err = Error;
+
+    /// A named use (`body` 16px/400, `heading` 24px/700, `caption`
+    /// 12px/400), or `SIZE/WEIGHT` with the size in px, e.g. `18/500`.
+    fn from_str(s: &str) -> Result<Self> {
+        let (size_px, weight) = match s {
+            "body" => (16., 400),
+            "heading" => (24., 700),
+            "caption" => (12., 400),
+            _ => {
+                let invalid = || Error::unknown("text use", s);
+                let (size, weight) = s.split_once('/').ok_or_else(invalid)?;
+                let size: f32 = size.parse().map_err(|_| invalid())?;
+                let weight: u16 = weight.parse().map_err(|_| invalid())?;
+                if !size.is_finite() || size <= 0. || !(1..=1000).contains(&weight) {
+                    return Err(invalid());
+                }
+                (size, weight)
+            }
+        };
+        Ok(TextUse { size_px, weight })
+    }
+}
+
+impl Display for TextUse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}px/{}", self.size_px, self.weight)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::rgb;
+
+    /// Reference values published with the apca-w3 package (0.0.98G-4g),
+    /// as (text, background, Lc).
+    const REFERENCE_LC: [(&str, &str, f32); 6] = [
+        ("#000000", "#ffffff", 106.04),
+        ("#ffffff", "#000000", -107.88),
+        ("#888888", "#ffffff", 63.06),
+        ("#ffffff", "#888888", -68.54),
+        ("#000000", "#aaaaaa", 58.15),
+        ("#aaaaaa", "#000000", -56.24),
+    ];
+
+    #[test]
+    fn lightness_contrast_matches_the_reference() {
+        for (text, bg, expected) in REFERENCE_LC {
+            let lc = lightness_contrast(rgb(text), rgb(bg), ColorSpace::Srgb);
+            assert!(
+                (lc - expected).abs() < 0.01,
+                "{text} on {bg}: Lc {lc}, expected {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn required_lc_reads_the_lookup_table() {
+        for (text_use, lc) in [
+            ("body", 90.),
+            ("heading", 45.),
+            ("caption", 120.),
+            ("18/500", 70.),
+            // Between columns, the lighter weight's sizes apply.
+            ("16/450", 90.),
+        ] {
+            let text_use: TextUse = text_use.parse().unwrap();
+            assert_eq!(text_use.required_lc(), lc, "{text_use}");
+        }
+    }
+}
@@ -0,0 +1,180 @@
+//! Adobe Swatch Exchange (`.ase`) files, for importing palettes into
+//! Adobe tools.
+//!
+//! The format is big-endian: the `ASEF` signature, version 1.0, a block
+//! count, then blocks of a type, a byte length and a body. Color blocks
+//! hold a UTF-16 name, a color model and float channels; group blocks
+//! bracket the colors of one palette.
+
+use crate::color::*;
+
+const SIGNATURE: &[u8; 4] = b"ASEF";
+const VERSION: (u16, u16) = (1, 0);
+const GROUP_START: u16 = 0xc001;
+const GROUP_END: u16 = 0xc002;
+const COLOR: u16 = 0x0001;
+const RGB_MODEL: &[u8; 4] = b"RGB ";
+/// Color type for a plain (not global or spot) swatch.
+const NORMAL: u16 = 2;
+
+/// Serializes one group per `(name, colors)` palette, with each color
+/// named by its hex code.
+pub fn swatches(palettes: &[(&str, &[Color])]) -> Vec<u8> {
+    let n_blocks: usize = palettes.iter().map(|(_, colors)| colors.len() + 2).sum();
+    let mut out = vec![];
+    out.extend(SIGNATURE);
+    out.extend(VERSION.0.to_be_bytes());
+    out.extend(VERSION.1.to_be_bytes());
+    out.extend((n_blocks as u32).to_be_bytes());
+    for (name, colors) in palettes {
+        write_block(&mut out, GROUP_START, &name_bytes(name));
+        for (c, hex) in colors.iter().zip(hex_colors(colors)) {
+            let mut body = name_bytes(&hex);
+            body.extend(RGB_MODEL);
+            for x in [c.red, c.green, c.blue] {
+                body.extend(x.to_be_bytes());
+            }
+            body.extend(NORMAL.to_be_bytes());
+            write_block(&mut out, COLOR, &body);
+        }
+        write_block(&mut out, GROUP_END, &[]);
+    }
+    out
+}
+
+fn write_block(out: &mut Vec<u8>, kind: u16, body: &[u8]) {
+    out.extend(kind.to_be_bytes());
+    out.extend((body.len() as u32).to_be_bytes());
+    out.extend(body);
+}
+
+/// A UTF-16 length (counting the terminator), then the name as
+/// null-terminated UTF-16.
+fn name_bytes(name: &str) -> Vec<u8> {
+    let units: Vec<u16> = name.encode_utf16().chain([0]).collect();
+    let mut out = (units.len() as u16).to_be_bytes().to_vec();
+    out.extend(units.iter().flat_map(|u| u.to_be_bytes()));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{Error, Result};
+
+    /// Reads back the groups written by [`swatches`], as (name, colors)
+    /// pairs. Only handles RGB colors inside groups.
+    fn read_swatches(bytes: &[u8]) -> Result<Vec<(String, Vec<Color>)>> {
+        let mut r = Reader { bytes, pos: 0 };
+        if r.take(4)? != SIGNATURE {
+            return Err(r.error("missing ASEF signature"));
+        }
+        if (r.u16()?, r.u16()?) != VERSION {
+            return Err(r.error("unsupported version"));
+        }
+        let n_blocks = r.u32()?;
+        let mut palettes: Vec<(String, Vec<Color>)> = vec![];
+        for _ in 0..n_blocks {
+            let kind = r.u16()?;
+            let len = r.u32()? as usize;
+            let mut body = Reader {
+                bytes: r.take(len)?,
+                pos: 0,
+            };
+            match kind {
+                GROUP_START => palettes.push((body.name()?, vec![])),
+                GROUP_END => {}
+                COLOR => {
+                    body.name()?;
+                    if body.take(4)? != RGB_MODEL {
+                        return Err(body.error("only RGB colors are supported"));
+                    }
+                    let (r, g, b) = (body.f32()?, body.f32()?, body.f32()?);
+                    let group = palettes
+                        .last_mut()
+                        .ok_or_else(|| body.error("color outside of a group"))?;
+                    group.1.push(Color::new(r, g, b));
+                }
+                _ => return Err(r.error("unknown block type")),
+            }
+        }
+        Ok(palettes)
+    }
+
+    struct Reader<'a> {
+        bytes: &'a [u8],
+        pos: usize,
+    }
+
+    impl<'a> Reader<'a> {
+        fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+            let bytes = self
+                .bytes
+                .get(self.pos..self.pos + n)
+                .ok_or_else(|| self.error("unexpected end of data"))?;
+            self.pos += n;
+            Ok(bytes)
+        }
+
+        fn u16(&mut self) -> Result<u16> {
+            Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+        }
+
+        fn u32(&mut self) -> Result<u32> {
+            Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+        }
+
+        fn f32(&mut self) -> Result<f32> {
+            Ok(f32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+        }
+
+        fn name(&mut self) -> Result<String> {
+            let n_units = self.u16()? as usize;
+            let units: Vec<u16> = self
+                .take(2 * n_units)?
+                .chunks(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .take_while(|&u| u != 0)
+                .collect();
+            String::from_utf16(&units).map_err(|_| self.error("invalid UTF-16 name"))
+        }
+
+        fn error(&self, message: &str) -> Error {
+            Error::Config {
+                path: "<ase>".to_string(),
+                message: format!("{message} at byte {}", self.pos),
+            }
+        }
+    }
+
+    /// A single red swatch in a group named "g".
+    #[rustfmt::skip]
+    const GOLDEN: [u8; 72] = [
+        b'A', b'S', b'E', b'F', 0, 1, 0, 0, 0, 0, 0, 3,
+        // Group start "g"
+        0xc0, 0x01, 0, 0, 0, 6, 0, 2, 0, b'g', 0, 0,
+        // Color "#ff0000": 1.0, 0.0, 0.0, normal
+        0, 1, 0, 0, 0, 36, 0, 8,
+        0, b'#', 0, b'f', 0, b'f', 0, b'0', 0, b'0', 0, b'0', 0, b'0', 0, 0,
+        b'R', b'G', b'B', b' ', 0x3f, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2,
+        // Group end
+        0xc0, 0x02, 0, 0, 0, 0,
+    ];
+
+    /// Checks the serializer byte for byte against [`GOLDEN`], and that a
+    /// larger file reads back to the same colors.
+    #[test]
+    fn swatches_match_the_golden_bytes_and_read_back() {
+        let red = [rgb("#ff0000")];
+        assert_eq!(swatches(&[("g", &red)]), GOLDEN, "ASE bytes changed");
+        let dark = colors_from_hex(&["#1d212f", "#ffc247", "#00a1c7"]).unwrap();
+        let light = colors_from_hex(&["#ffffff", "#820dde"]).unwrap();
+        let palettes: [(&str, &[Color]); 2] = [("dark", &dark), ("light", &light)];
+        let read = read_swatches(&swatches(&palettes)).expect("Written swatches parse");
+        assert_eq!(read.len(), palettes.len());
+        for ((name, colors), (read_name, read_colors)) in palettes.iter().zip(&read) {
+            assert_eq!(name, read_name);
+            assert_eq!(hex_colors(colors), hex_colors(read_colors));
+        }
+    }
+}
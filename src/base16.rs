@@ -0,0 +1,261 @@
+use std::{fmt::Write, str::FromStr};
+
+use crate::color::*;
+use crate::error::{Error, Result};
+use crate::math::cmp_nan_last;
+
+/// Where a Base16/Base24 slot takes its color from.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// Index into the active background colors (`main` is 0).
+    Bg(usize),
+    /// Index into the foreground colors.
+    Fg(usize),
+}
+
+impl FromStr for Source {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let usage = || Error::Usage(format!("Expected bg:N or fg:N, got {s}"));
+        let (kind, index) = s.split_once(':').ok_or_else(usage)?;
+        let index = index
+            .parse()
+            .map_err(|_| Error::Usage(format!("Invalid color index in {s}")))?;
+        match kind {
+            "bg" => Ok(Source::Bg(index)),
+            "fg" => Ok(Source::Fg(index)),
+            _ => Err(usage()),
+        }
+    }
+}
+
+/// Parses an override like `base08=fg:3`.
+pub fn parse_override(s: &str) -> Result<(usize, Source)> {
+    let (slot, source) = s
+        .split_once('=')
+        .ok_or_else(|| Error::Usage(format!("Expected baseXX=SOURCE, got {s}")))?;
+    let slot = slot
+        .strip_prefix("base")
+        .and_then(|n| usize::from_str_radix(n, 16).ok())
+        .filter(|n| *n < 24)
+        .ok_or_else(|| Error::unknown("Base16 slot", slot))?;
+    Ok((slot, source.parse()?))
+}
+
+// Reference hues for the accent slots, in slot order: base08..base0F, then
+// Base24's bright variants base12..base17.
+const ACCENTS: [&str; 8] = [
+    "#ff0000", // red
+    "#ff8000", // orange
+    "#ffff00", // yellow
+    "#00ff00", // green
+    "#00ffff", // cyan
+    "#0000ff", // blue
+    "#ff00ff", // magenta
+    "#804000", // brown
+];
+const BRIGHT_ACCENTS: [usize; 6] = [0, 2, 3, 4, 5, 6];
+
+/// The default mapping from our roles to the 24 Base24 slots (the first 16
+/// are Base16):
+///
+/// - base00 is the main background; base01 and base02 (status bar and
+///   selection backgrounds) are the line selection background, if any.
+/// - base03..base07, the foreground ramp, are the five least chromatic
+///   foreground colors, ordered by lightness away from the main background.
+/// - base08..base0F, the accents, are the remaining foreground colors
+///   closest in hue to red, orange, yellow, green, cyan, blue, magenta and
+///   brown. Each color is used at most once while unused ones remain.
+/// - base10 and base11 (darker backgrounds) are the main background, and
+///   base12..base17 are the next-closest colors to the bright accents.
+pub fn default_mapping(bg: &[Color], fg: &[Color], space: ColorSpace) -> Result<[Source; 24]> {
+    assert!(!bg.is_empty(), "The main background is always present");
+    if fg.is_empty() {
+        return Err(Error::TooFewColors { needed: 1, got: 0 });
+    }
+    let main = Source::Bg(0);
+    let selection = Source::Bg(bg.len().min(2) - 1);
+    let mut out = [main; 24];
+    out[1] = selection;
+    out[2] = selection;
+
+    let lch: Vec<_> = fg.iter().map(|c| to_lch(*c, space)).collect();
+    let mut used = vec![false; fg.len()];
+
+    let mut ramp: Vec<usize> = (0..fg.len()).collect();
+    ramp.sort_by(|&i, &j| cmp_nan_last(lch[i].chroma, lch[j].chroma));
+    ramp.truncate(5);
+    ramp.sort_by(|&i, &j| cmp_nan_last(lch[i].l, lch[j].l));
+    if to_lch(bg[0], space).l > 50. {
+        // Dark-on-light: the ramp runs from light to dark.
+        ramp.reverse();
+    }
+    for (k, &i) in ramp.iter().enumerate() {
+        out[3 + k] = Source::Fg(i);
+        used[i] = true;
+    }
+
+    let closest_unused = |reference: &'static str, used: &mut Vec<bool>| {
+        let hue = to_lch(rgb(reference), space).hue;
+        let mut candidates: Vec<usize> = (0..fg.len()).filter(|i| !used[*i]).collect();
+        if candidates.is_empty() {
+            candidates = (0..fg.len()).collect();
+        }
+        let best = candidates
+            .into_iter()
+            .min_by(|&i, &j| {
+                let d = |k: usize| (lch[k].hue - hue).to_degrees().abs();
+                cmp_nan_last(d(i), d(j))
+            })
+            .expect("At least one foreground color");
+        used[best] = true;
+        Source::Fg(best)
+    };
+    for (k, reference) in ACCENTS.iter().enumerate() {
+        out[8 + k] = closest_unused(reference, &mut used);
+    }
+    for (k, accent) in BRIGHT_ACCENTS.iter().enumerate() {
+        out[0x12 + k] = closest_unused(ACCENTS[*accent], &mut used);
+    }
+    Ok(out)
+}
+
+/// Checks that a scheme can be rendered from `n_bg` backgrounds and `n_fg`
+/// foregrounds: the accents need at least one foreground color, and every
+/// override has to name an existing color.
+pub fn check_sources(n_bg: usize, n_fg: usize, overrides: &[(usize, Source)]) -> Result<()> {
+    if n_fg == 0 {
+        return Err(Error::TooFewColors { needed: 1, got: 0 });
+    }
+    for (slot, source) in overrides {
+        let (kind, i, n) = match *source {
+            Source::Bg(i) => ("background", i, n_bg),
+            Source::Fg(i) => ("foreground", i, n_fg),
+        };
+        if i >= n {
+            return Err(Error::Usage(format!(
+                "--base16-map base{slot:02X}: {kind} index {i} out of range; there are {n} \
+                 {kind} colors"
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Renders a Base16 (or, with `base24`, Base24) scheme YAML. Overrides are
+/// (slot, source) pairs applied on top of [`default_mapping`].
+pub fn scheme_yaml(
+    scheme: &str,
+    bg: &[Color],
+    fg: &[Color],
+    overrides: &[(usize, Source)],
+    base24: bool,
+    space: ColorSpace,
+) -> Result<String> {
+    check_sources(bg.len(), fg.len(), overrides)?;
+    let mut mapping = default_mapping(bg, fg, space)?;
+    for (slot, source) in overrides {
+        mapping[*slot] = *source;
+    }
+    let n_slots = if base24 { 24 } else { 16 };
+
+    let mut out = String::new();
+    writeln!(out, "scheme: \"{scheme}\"").unwrap();
+    writeln!(out, "author: \"category-colors\"").unwrap();
+    for (slot, source) in mapping.iter().take(n_slots).enumerate() {
+        let color = match *source {
+            Source::Bg(i) => bg[i],
+            Source::Fg(i) => fg[i],
+        };
+        let hex = &hex_colors(&[color])[0];
+        writeln!(out, "base{slot:02X}: \"{}\"", hex.trim_start_matches('#')).unwrap();
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sg::Mode;
+
+    fn fg_index(source: Source) -> usize {
+        match source {
+            Source::Fg(i) => i,
+            Source::Bg(_) => panic!("{source:?} is not a foreground color"),
+        }
+    }
+
+    #[test]
+    fn ramp_runs_away_from_the_main_background() {
+        let space = ColorSpace::Srgb;
+        for mode in [Mode::Dark, Mode::Light] {
+            let (bg, fg) = (mode.bg_colors().into_array(), mode.brand_colors());
+            let mapping = default_mapping(&bg, &fg, space).unwrap();
+            let lightness: Vec<f32> = mapping[3..8]
+                .iter()
+                .map(|s| to_lch(fg[fg_index(*s)], space).l)
+                .collect();
+            let mut sorted = lightness.clone();
+            sorted.sort_by(|a, b| cmp_nan_last(*a, *b));
+            if matches!(mode, Mode::Light) {
+                sorted.reverse();
+            }
+            assert_eq!(lightness, sorted, "{} mode ramp", mode.text());
+        }
+    }
+
+    #[test]
+    fn accents_use_each_color_at_most_once() {
+        let space = ColorSpace::Srgb;
+        let (bg, fg) = (
+            Mode::Dark.bg_colors().into_array(),
+            Mode::Dark.brand_colors(),
+        );
+        let mapping = default_mapping(&bg, &fg, space).unwrap();
+        // Ramp, accents and bright accents need 19 of the 23 brand colors.
+        let mut used: Vec<usize> = mapping[3..0x10]
+            .iter()
+            .chain(&mapping[0x12..])
+            .map(|s| fg_index(*s))
+            .collect();
+        used.sort();
+        used.dedup();
+        assert_eq!(used.len(), 5 + ACCENTS.len() + BRIGHT_ACCENTS.len());
+    }
+
+    #[test]
+    fn overrides_parse_slot_and_source() {
+        assert_eq!(parse_override("base08=fg:3").unwrap(), (8, Source::Fg(3)));
+        assert_eq!(
+            parse_override("base17=bg:1").unwrap(),
+            (0x17, Source::Bg(1))
+        );
+        for bad in [
+            "base18=fg:0",
+            "base0G=fg:0",
+            "base08",
+            "base08=xx:1",
+            "base08=fg:x",
+        ] {
+            assert!(parse_override(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn overrides_replace_the_default_slot() {
+        let space = ColorSpace::Srgb;
+        let (bg, fg) = (
+            Mode::Dark.bg_colors().into_array(),
+            Mode::Dark.brand_colors(),
+        );
+        let yaml = scheme_yaml("t", &bg, &fg, &[(8, Source::Bg(0))], false, space).unwrap();
+        let main = hex_colors(&bg[..1])[0].trim_start_matches('#').to_string();
+        assert!(yaml.contains(&format!("base08: \"{main}\"")), "{yaml}");
+
+        let result = scheme_yaml("t", &bg, &fg, &[(8, Source::Fg(99))], false, space);
+        assert!(matches!(result, Err(Error::Usage(_))));
+        let result = scheme_yaml("t", &bg, &[], &[], false, space);
+        assert!(matches!(result, Err(Error::TooFewColors { .. })));
+    }
+}
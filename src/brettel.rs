@@ -1,4 +1,7 @@
+use std::str::FromStr;
+
 use crate::color::*;
+use crate::error::{Error, Result};
 
 pub struct BrettelParams {
     rgb_cvd_from_rgb_1: [f32; 9],
@@ -6,18 +9,157 @@ pub struct BrettelParams {
     separation_plane_normal: [f32; 3],
 }
 
-pub fn brettel_function(c: Color, v: Vision) -> Color {
-    // TODO: How to describe what this does?
+/// Which of a CVD type's two projection planes a color maps onto.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BrettelPlane {
+    /// `rgb_cvd_from_rgb_1`, for colors on the non-negative side of the
+    /// separation plane.
+    First,
+    /// `rgb_cvd_from_rgb_2`, for the rest.
+    Second,
+}
+
+impl BrettelParams {
+    /// The plane for linear RGB `rgb`, and its dot product with the
+    /// separation plane normal that decided it.
+    fn plane(&self, rgb: (f32, f32, f32)) -> (BrettelPlane, f32) {
+        let n = self.separation_plane_normal;
+        let dot = rgb.0 * n[0] + rgb.1 * n[1] + rgb.2 * n[2];
+        let plane = if dot >= 0. {
+            BrettelPlane::First
+        } else {
+            BrettelPlane::Second
+        };
+        (plane, dot)
+    }
+
+    fn matrix(&self, plane: BrettelPlane) -> &[f32; 9] {
+        match plane {
+            BrettelPlane::First => &self.rgb_cvd_from_rgb_1,
+            BrettelPlane::Second => &self.rgb_cvd_from_rgb_2,
+        }
+    }
+}
+
+/// Multiplies linear RGB `rgb` by the row-major matrix `m`.
+fn project(m: &[f32; 9], rgb: (f32, f32, f32)) -> (f32, f32, f32) {
+    (
+        m[0] * rgb.0 + m[1] * rgb.1 + m[2] * rgb.2,
+        m[3] * rgb.0 + m[4] * rgb.1 + m[5] * rgb.2,
+        m[6] * rgb.0 + m[7] * rgb.1 + m[8] * rgb.2,
+    )
+}
+
+/// The plane that the simulation of `v` projects `c` onto, and the dot
+/// product with the separation plane normal that chose it; a dot near zero
+/// means `c` sits on the boundary. `None` for visions without planes.
+pub fn brettel_plane(c: Color, v: Vision, space: ColorSpace) -> Option<(BrettelPlane, f32)> {
+    let params = brettel_params(v)?;
+    let rgb = LinearRgb::from_encoding(srgb_appearance(c, space)).into_components();
+    Some(params.plane(rgb))
+}
+
+/// Luminance coefficients used to simulate achromatic vision.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Luma {
+    /// 0.299/0.587/0.114, applied to the gamma-encoded channels.
+    #[default]
+    Rec601,
+    /// 0.2126/0.7152/0.0722, applied in linear light. This is the relative
+    /// luminance modern displays are specified against.
+    Rec709,
+}
+
+impl Luma {
+    pub fn text(self) -> &'static str {
+        match self {
+            Luma::Rec601 => "rec601",
+            Luma::Rec709 => "rec709",
+        }
+    }
+}
+
+impl FromStr for Luma {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "rec601" => Ok(Luma::Rec601),
+            "rec709" => Ok(Luma::Rec709),
+            _ => Err(Error::unknown("luma coefficients", s)),
+        }
+    }
+}
+
+pub fn brettel_function(c: Color, v: Vision, space: ColorSpace) -> Color {
+    brettel_function_at(c, v, default_severity(v), Luma::default(), space)
+}
+
+/// Like [`simulate_vision`], at `severity` in [0, 1] rather than the
+/// default for `v`. 0 is normal vision and 1 the full deficiency, so e.g.
+/// protanopia at 0.4 is a mild protanomaly.
+pub fn brettel_function_at(
+    c: Color,
+    v: Vision,
+    severity: f32,
+    luma: Luma,
+    space: ColorSpace,
+) -> Color {
+    simulate_at(c, v, severity, luma, space)
+}
+
+/// Like [`brettel_function`], with a choice of coefficients for the
+/// achromatic types.
+pub fn simulate_vision(c: Color, v: Vision, luma: Luma, space: ColorSpace) -> Color {
+    simulate_at(c, v, default_severity(v), luma, space)
+}
+
+/// Severity of the anomalous ("-omaly") types unless given otherwise.
+const ANOMALOUS_SEVERITY: f32 = 0.6;
+
+fn default_severity(v: Vision) -> f32 {
+    use Vision::*;
+    match v {
+        Default => 0.,
+        Protanopia | Deuteranopia | Tritanopia | Achromatopsia => 1.,
+        Protonomaly | Deuteranomaly | Tritanomaly | Achromatomaly => ANOMALOUS_SEVERITY,
+    }
+}
+
+fn simulate_at(c: Color, v: Vision, severity: f32, luma: Luma, space: ColorSpace) -> Color {
+    if let Vision::Default = v {
+        return c;
+    }
+    // The simulation works on how the color looks, in sRGB terms; map the
+    // result back to the working space like every other color.
+    from_srgb_appearance(
+        simulate_srgb(srgb_appearance(c, space), v, severity, luma),
+        space,
+    )
+}
+
+/// Each "-opia" type and its "-omaly" counterpart share a simulation, which
+/// `severity` blends with the unchanged color.
+fn simulate_srgb(c: Color, v: Vision, severity: f32, luma: Luma) -> Color {
     use Vision::*;
     match v {
         Default => c,
-        Achromatomaly => monochrome_with_severity(c, 0.6),
-        Achromatopsia => monochrome_with_severity(c, 1.0),
-        Protanopia | Deuteranopia | Tritanopia => brettel(c, v, 1.0),
-        Protonomaly | Deuteranomaly | Tritanomaly => brettel(c, v, 0.6),
+        Achromatomaly | Achromatopsia => monochrome_with_severity(c, severity, luma),
+        Protanopia | Deuteranopia | Tritanopia | Protonomaly | Deuteranomaly | Tritanomaly => {
+            brettel(c, v, severity)
+        }
     }
 }
 
+/// CIEDE2000 distance between two colors as seen with vision `v`.
+pub fn distance_under_vision(a: Color, b: Color, v: Vision, space: ColorSpace) -> f32 {
+    distance(
+        brettel_function(a, v, space),
+        brettel_function(b, v, space),
+        space,
+    )
+}
+
 fn brettel_params(v: Vision) -> Option<BrettelParams> {
     use Vision::*;
     match v {
@@ -54,32 +196,15 @@ fn brettel_params(v: Vision) -> Option<BrettelParams> {
 
 fn brettel(c_srgb: Color, v: Vision, severity: f32) -> Color {
     let c_lrgb = LinearRgb::from_encoding(c_srgb);
-    let params = brettel_params(v).expect(&format!("Unexpected vision {:?}", v));
-
-    let separation_plane_normal = params.separation_plane_normal;
-    let rgb_cvd_from_rgb_1 = params.rgb_cvd_from_rgb_1;
-    let rgb_cvd_from_rgb_2 = params.rgb_cvd_from_rgb_2;
+    let params = brettel_params(v).unwrap_or_else(|| panic!("Unexpected vision {:?}", v));
 
     let rgb = c_lrgb.into_components();
 
     // Check on which plane we should project by comparing wih the separation plane normal.
-    let dot_with_sep_plane = rgb.0 * separation_plane_normal[0]
-        + rgb.1 * separation_plane_normal[1]
-        + rgb.2 * separation_plane_normal[2];
-    let rgb_cvd_from_rgb = if dot_with_sep_plane >= 0. {
-        rgb_cvd_from_rgb_1
-    } else {
-        rgb_cvd_from_rgb_2
-    };
+    let (plane, _) = params.plane(rgb);
 
     // Transform to the full dichromat projection plane.
-    let mut rgb_cvd = (0., 0., 0.);
-    rgb_cvd.0 =
-        rgb_cvd_from_rgb[0] * rgb.0 + rgb_cvd_from_rgb[1] * rgb.1 + rgb_cvd_from_rgb[2] * rgb.2;
-    rgb_cvd.1 =
-        rgb_cvd_from_rgb[3] * rgb.0 + rgb_cvd_from_rgb[4] * rgb.1 + rgb_cvd_from_rgb[5] * rgb.2;
-    rgb_cvd.2 =
-        rgb_cvd_from_rgb[6] * rgb.0 + rgb_cvd_from_rgb[7] * rgb.1 + rgb_cvd_from_rgb[8] * rgb.2;
+    let mut rgb_cvd = project(params.matrix(plane), rgb);
 
     // Apply the severity factor as a linear interpolation.
     // It's the same to do it in the RGB space or in the LMS
@@ -92,11 +217,202 @@ fn brettel(c_srgb: Color, v: Vision, severity: f32) -> Color {
     Color::from_encoding(LinearRgb::from_components(rgb_cvd))
 }
 
-fn monochrome_with_severity(c: Color, severity: f32) -> Color {
-    let srgb = c.into_components();
-    let z = (srgb.0 * 0.299 + srgb.1 * 0.587 + srgb.2 * 0.114).round();
-    let r = z * severity + (1.0 - severity) * srgb.0;
-    let g = z * severity + (1.0 - severity) * srgb.1;
-    let b = z * severity + (1.0 - severity) * srgb.2;
-    return Color::from_components((r, g, b));
+fn monochrome_with_severity(c: Color, severity: f32, luma: Luma) -> Color {
+    match luma {
+        Luma::Rec601 => {
+            let srgb = c.into_components();
+            // FIXME: round() assumes 0..=255 channels, so this snaps every
+            // color to black or white.
+            let z = (srgb.0 * 0.299 + srgb.1 * 0.587 + srgb.2 * 0.114).round();
+            let r = z * severity + (1.0 - severity) * srgb.0;
+            let g = z * severity + (1.0 - severity) * srgb.1;
+            let b = z * severity + (1.0 - severity) * srgb.2;
+            Color::from_components((r, g, b))
+        }
+        Luma::Rec709 => {
+            let lrgb = LinearRgb::from_encoding(c).into_components();
+            let y = lrgb.0 * 0.2126 + lrgb.1 * 0.7152 + lrgb.2 * 0.0722;
+            let r = y * severity + (1.0 - severity) * lrgb.0;
+            let g = y * severity + (1.0 - severity) * lrgb.1;
+            let b = y * severity + (1.0 - severity) * lrgb.2;
+            Color::from_encoding(LinearRgb::from_components((r, g, b)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Inputs for [`golden_outputs_match`]. For each CVD type the primaries fall on
+    /// both sides of the separation plane, so both matrices are exercised.
+    const GOLDEN_INPUTS: [&str; 5] = ["#ff0000", "#00ff00", "#0000ff", "#ff8000", "#808080"];
+
+    /// Outputs for [`GOLDEN_INPUTS`], recorded from this implementation, so
+    /// they only catch changes to it; [`matrices_are_brettel_projections`]
+    /// checks the matrices themselves. Gray must map to itself.
+    const GOLDEN_OUTPUTS: [(Vision, [&str; 5]); 6] = [
+        (
+            Vision::Protanopia,
+            ["#6a5b0e", "#ffee00", "#0037ff", "#aa920a", "#808080"],
+        ),
+        (
+            Vision::Protonomaly,
+            ["#b94708", "#ddf500", "#0029ff", "#d28b06", "#808080"],
+        ),
+        (
+            Vision::Deuteranopia,
+            ["#a48b00", "#f2d12e", "#0056fe", "#c5a900", "#808080"],
+        ),
+        (
+            Vision::Deuteranomaly,
+            ["#cf6e00", "#c1e523", "#0043ff", "#df9a00", "#808080"],
+        ),
+        (
+            Vision::Tritanopia,
+            ["#ff004e", "#7ceaff", "#006087", "#ff758a", "#808080"],
+        ),
+        (
+            Vision::Tritanomaly,
+            ["#ff003d", "#61f3d7", "#004bc3", "#ff796c", "#808080"],
+        ),
+    ];
+
+    /// Checks the simulation of a few fixed colors against recorded outputs,
+    /// allowing one step of 8-bit rounding per channel. Guards the matrices
+    /// and the separation plane test against accidental edits.
+    #[test]
+    fn golden_outputs_match() {
+        let space = ColorSpace::Srgb;
+        for (v, outputs) in GOLDEN_OUTPUTS {
+            for (input, expected) in GOLDEN_INPUTS.iter().zip(outputs) {
+                let actual = brettel_function(rgb(input), v, space).into_format::<u8>();
+                let expected = rgb(expected).into_format::<u8>();
+                let close = |a: u8, b: u8| a.abs_diff(b) <= 1;
+                assert!(
+                    close(actual.red, expected.red)
+                        && close(actual.green, expected.green)
+                        && close(actual.blue, expected.blue),
+                    "{} of {input} is {}, expected {}",
+                    v.text(),
+                    hex_colors(&[actual.into_format()])[0],
+                    hex_colors(&[expected.into_format()])[0],
+                );
+            }
+        }
+    }
+
+    /// Checks the properties Brettel's construction gives each matrix, which
+    /// a mistyped coefficient breaks: it projects onto a plane through the
+    /// neutral axis, so it keeps white, has rank 2 and applying it twice
+    /// changes nothing. The separation plane contains the neutral axis too.
+    /// The tolerances allow for the coefficients' five decimal places.
+    #[test]
+    fn matrices_are_brettel_projections() {
+        const TOLERANCE: f32 = 2e-5;
+        for v in [Vision::Protanopia, Vision::Deuteranopia, Vision::Tritanopia] {
+            let params = brettel_params(v).unwrap();
+            let n = params.separation_plane_normal;
+            assert!(
+                (n[0] + n[1] + n[2]).abs() < TOLERANCE,
+                "{} normal",
+                v.text()
+            );
+            for plane in [BrettelPlane::First, BrettelPlane::Second] {
+                let m = params.matrix(plane);
+                for row in m.chunks(3) {
+                    let sum: f32 = row.iter().sum();
+                    assert!(
+                        (sum - 1.).abs() < TOLERANCE,
+                        "{} {plane:?} row {row:?}",
+                        v.text()
+                    );
+                }
+                let det = m[0] * (m[4] * m[8] - m[5] * m[7]) - m[1] * (m[3] * m[8] - m[5] * m[6])
+                    + m[2] * (m[3] * m[7] - m[4] * m[6]);
+                assert!(
+                    det.abs() < TOLERANCE,
+                    "{} {plane:?} determinant {det}",
+                    v.text()
+                );
+                for col in 0..3 {
+                    let x = (m[col], m[3 + col], m[6 + col]);
+                    let twice = project(m, x);
+                    let diff = [twice.0 - x.0, twice.1 - x.1, twice.2 - x.2];
+                    assert!(
+                        diff.iter().all(|d| d.abs() < TOLERANCE),
+                        "{} {plane:?} is not a projection: {diff:?}",
+                        v.text()
+                    );
+                }
+            }
+        }
+    }
+
+    /// Checks that each dichromat simulation is continuous across its
+    /// separation plane: bisecting from a corner of the RGB cube on one side
+    /// to a corner on the other, both matrices must map the crossing point to
+    /// the same color. The golden outputs only cover colors far from the
+    /// plane, so this is what catches a mistyped normal or matrix entry.
+    #[test]
+    fn continuous_across_separation_planes() {
+        let space = ColorSpace::Srgb;
+        const CORNERS: [(f32, f32, f32); 7] = [
+            (1., 0., 0.),
+            (0., 1., 0.),
+            (0., 0., 1.),
+            (1., 1., 0.),
+            (1., 0., 1.),
+            (0., 1., 1.),
+            (1., 1., 1.),
+        ];
+        // In linear RGB, against channel values of up to 1.
+        const MAX_GAP: f32 = 1e-3;
+        let as_color = |rgb| {
+            from_srgb_appearance(Color::from_encoding(LinearRgb::from_components(rgb)), space)
+        };
+        for v in [Vision::Protanopia, Vision::Deuteranopia, Vision::Tritanopia] {
+            let params = brettel_params(v).unwrap();
+            let mut n_crossings = 0;
+            for a in CORNERS {
+                for b in CORNERS {
+                    let on =
+                        |rgb, plane| brettel_plane(as_color(rgb), v, space).unwrap().0 == plane;
+                    if !on(a, BrettelPlane::First) || !on(b, BrettelPlane::Second) {
+                        continue;
+                    }
+                    let lerp = |t: f32| {
+                        (
+                            a.0 + t * (b.0 - a.0),
+                            a.1 + t * (b.1 - a.1),
+                            a.2 + t * (b.2 - a.2),
+                        )
+                    };
+                    let (mut lo, mut hi) = (0f32, 1f32);
+                    for _ in 0..40 {
+                        let mid = (lo + hi) / 2.;
+                        if params.plane(lerp(mid)).0 == BrettelPlane::First {
+                            lo = mid;
+                        } else {
+                            hi = mid;
+                        }
+                    }
+                    let crossing = lerp(lo);
+                    let first = project(params.matrix(BrettelPlane::First), crossing);
+                    let second = project(params.matrix(BrettelPlane::Second), crossing);
+                    let gap = (first.0 - second.0)
+                        .abs()
+                        .max((first.1 - second.1).abs())
+                        .max((first.2 - second.2).abs());
+                    assert!(
+                        gap <= MAX_GAP,
+                        "{} jumps by {gap} across its separation plane at {crossing:?}",
+                        v.text()
+                    );
+                    n_crossings += 1;
+                }
+            }
+            assert!(n_crossings > 0, "{} plane separates no corners", v.text());
+        }
+    }
 }
@@ -0,0 +1,862 @@
+use std::{str::FromStr, time::Duration};
+
+use crate::{
+    apca::TextUse,
+    base16,
+    brettel::Luma,
+    color::{colors_from_hex, Color, ColorSpace, HueBand, Perturbation, Vision},
+    config::RunConfig,
+    cost::{
+        required_ratio, ContrastLevel, ContrastNeed, ContrastSigmoid, CvdWeights, Preset,
+        ProtectedPair,
+    },
+    error::{Error, Result},
+    math::Precision,
+    random::{seed_from_hex, Seed},
+    sg::{BackgroundColors, BgRole},
+};
+
+/// Command-line options.
+///
+/// Usage: `category-colors [simulate COLOR... | grayscale [COLOR...] | compare
+/// | stability | cost | minimize | SEED]
+/// [--config FILE] [--seed HEX]
+/// [--budget SECS]
+/// [--format table|summary|json|base16|base24|ase|contrast-json[,FORMAT]...] [--output FILE] [--base16-map baseXX=bg:N|fg:N]...
+/// [--quiet|--verbose] [--progress] [--shuffle] [--keep-hue-order] [--pin-chroma] [--hue-band warm|cool] [--feasible-start] [--protect FG_INDEX:BG_ROLE:MIN_RATIO]...
+/// [--restarts N] [--threads N] [--seeds K] [--background-only] [--sort-output hue|none]
+/// [--vision-table VISION] [--wiggle X] [--anneal-wiggle]
+/// [--perturb srgb|okhsl] [--iterations N] [--add-color]
+/// [--importance FG_INDEX:WEIGHT]... [--text-use FG_INDEX:USE]... [--luma rec601|rec709]
+/// [--backgrounds FG_INDEX:BG_ROLE[,BG_ROLE]...]... [--achromatic BG_ROLE[,BG_ROLE]...]
+/// [--preset balanced|accessibility-first|brand-faithful] [--weights FILE]
+/// [--cvd-weights custom|equal|prevalence] [--cvd-severity S] [--no-cvd] [--no-target]
+/// [--distance-objective rms|maximin]
+/// [--vs-preset PRESET] [--vs-weights FILE]
+/// [--phases joint|alternating] [--phase-rounds N] [--acceptance metropolis|threshold|greedy] [--contrast-level aa|aaa]
+/// [--contrast-steepness K] [--contrast-center RATIO]
+/// [--color-preview] [--color-space srgb|display-p3] [--precision f32|f64] [--snap-to COLOR[,COLOR]...]
+/// [--batch GLOB [--batch-dir DIR]]
+/// [--contrast-floor] [--dark-bg COLOR] [--light-bg COLOR] [--generate N] [--candidates COLOR[,COLOR]...] [--palette FILE] [--targets FILE]`
+#[derive(Clone)]
+pub struct Options {
+    pub command: Command,
+    /// Up to 32 bytes of the string are used to seed the RNG.
+    pub seed: Option<String>,
+    /// An exact seed as printed in the report, as 64 hex digits.
+    pub seed_bytes: Option<Seed>,
+    /// Anneal for this long instead of following the cooling schedule.
+    pub budget: Option<Duration>,
+    /// How the final report is printed; several formats come from one run
+    /// (see [`Options::format_output`]).
+    pub formats: Vec<Format>,
+    /// Write the final report here instead of stdout. The contrast tables
+    /// still go to stdout, and progress to stderr.
+    pub output: Option<String>,
+    pub verbosity: Verbosity,
+    /// Print the temperature, step count, best cost so far and acceptance
+    /// rate to stderr every 100 temperature steps, as `--verbose` does,
+    /// without changing what goes to stdout.
+    pub progress: bool,
+    /// Randomize the order in which colors are perturbed at each step.
+    pub shuffle: bool,
+    /// Pairs that must stay above a contrast ratio, e.g. `3:main:7`. Also
+    /// holds the pairs from `--backgrounds`, which must meet the text
+    /// minimum for `contrast_level` on every listed background.
+    pub protected_pairs: Vec<ProtectedPair>,
+    /// Number of independent optimizer runs; the best one is reported.
+    pub restarts: usize,
+    /// Size of the thread pool the restarts, and the files of a `--batch`
+    /// run, run on. Doesn't affect results.
+    pub threads: usize,
+    /// Per-color weights, e.g. `3:4` makes foreground 3 count four times
+    /// as much in the distance and target terms. These multiply with the
+    /// global term weights; unlisted colors weigh 1.
+    pub importance: Vec<(usize, f32)>,
+    /// What some foreground colors are used for, e.g. `0:body` or
+    /// `3:18/500` (px/weight). Those colors are held to the APCA Lc for
+    /// that size and weight instead of the WCAG text minimum.
+    pub text_uses: Vec<(usize, TextUse)>,
+    /// Backgrounds that must stay gray, with their LCH chroma held at or
+    /// below a small threshold. Only modifiable backgrounds qualify.
+    pub achromatic: Vec<BgRole>,
+    /// Keep the foreground colors fixed and only tune the backgrounds.
+    pub background_only: bool,
+    /// Never let the foreground colors change their order by LCH hue, for
+    /// sequential or diverging palettes whose order carries meaning.
+    pub keep_hue_order: bool,
+    /// Hold each foreground's LCH chroma at its starting value, varying
+    /// only hue and lightness, for palettes that should stay harmonious.
+    pub pin_chroma: bool,
+    /// Keep every foreground in a warm or cool band of hues: colors start
+    /// pulled into it and moves out of it are rejected. Near-grays have no
+    /// hue to speak of and are left alone.
+    pub hue_band: Option<HueBand>,
+    /// Before annealing, move each foreground that fails text contrast on
+    /// the main background to the nearest lightness that passes.
+    pub feasible_start: bool,
+    pub sort_output: SortOutput,
+    /// Draw the palettes in truecolor alongside the tables, when stdout is
+    /// a terminal.
+    pub color_preview: bool,
+    /// Also print the final fg ↔ fg distances as seen with this vision.
+    pub vision_table: Option<Vision>,
+    /// Perturbation step size for a color channel.
+    pub wiggle: f32,
+    /// Shrink the step size along with the temperature.
+    pub anneal_wiggle: bool,
+    /// Color space the step size applies in.
+    pub perturbation: Perturbation,
+    /// Stop after this many temperature steps.
+    pub max_iterations: Option<u64>,
+    /// Anneal all colors together, or backgrounds and foregrounds in turn.
+    pub phases: Phases,
+    /// Background-then-foreground rounds for alternating phases.
+    pub phase_rounds: usize,
+    /// When the optimizer takes a move that raises the cost.
+    pub acceptance_rule: AcceptanceRule,
+    /// Base16/Base24 slots to take from a specific color, e.g.
+    /// `base08=fg:3`.
+    pub base16_overrides: Vec<(usize, base16::Source)>,
+    /// Optimize this many random foreground colors, with no targets,
+    /// instead of the brand colors.
+    pub generate: Option<usize>,
+    /// After optimizing, adjust the lightness of any foreground below the
+    /// text contrast minimum until it passes.
+    pub contrast_floor: bool,
+    /// Main (editor canvas) background to use instead of the built-in one
+    /// in dark and light mode.
+    pub dark_bg: Option<Color>,
+    pub light_bg: Option<Color>,
+    /// File of hex colors to start from instead of the brand colors.
+    pub palette_file: Option<String>,
+    /// Optimize every palette file matching this glob, as if each were
+    /// given to `--palette`, in parallel. Each report is written next to
+    /// its palette, or into `batch_dir`.
+    pub batch: Option<String>,
+    pub batch_dir: Option<String>,
+    /// File of hex colors for the target term to pull towards, instead of
+    /// the starting colors.
+    pub targets_file: Option<String>,
+    /// Approved colors to snap the optimized foregrounds to, reporting what
+    /// that costs.
+    pub snap_to: Vec<Color>,
+    /// Only ever assign foreground colors from this set, e.g. a design
+    /// system's approved tokens.
+    pub candidates: Vec<Color>,
+    /// Also suggest one more foreground color for the optimized palette.
+    pub add_color: bool,
+    /// The RGB space hex colors are interpreted in, both built-in and
+    /// user-supplied.
+    pub color_space: ColorSpace,
+    /// The float type cost sums accumulate in.
+    pub precision: Precision,
+    /// WCAG level that the contrast cost and tables check against.
+    pub contrast_level: ContrastLevel,
+    /// Steepness and center of the contrast cost above the minimum ratio;
+    /// see [`ContrastSigmoid`] for how they trade a bare pass against
+    /// maximal contrast.
+    pub contrast_sigmoid: ContrastSigmoid,
+    /// Starting point for the cost weights.
+    pub preset: Preset,
+    /// JSON object whose fields override those of the preset's weights,
+    /// e.g. `{"contrast_weight": 3}`.
+    pub weights_file: Option<String>,
+    /// How the CVD terms share their total weight; applies to both
+    /// configurations of `compare`.
+    pub cvd_weights: CvdWeights,
+    /// Severity in [0, 1] at which the CVD terms simulate each deficiency,
+    /// e.g. 0.4 to optimize for mild anomalous trichromacy rather than
+    /// full dichromacy (1).
+    pub cvd_severity: f32,
+    /// How pairwise distances are turned into the distance terms (including
+    /// their CVD counterparts).
+    pub distance_objective: DistanceObjective,
+    /// Drop the CVD terms, skipping their (slow) simulation, for quick
+    /// iteration on everything else.
+    pub no_cvd: bool,
+    /// Drop the target term, so nothing pulls colors towards the starting
+    /// (or `--targets`) palette.
+    pub no_target: bool,
+    /// Grayscale coefficients for simulating achromatic vision, in
+    /// `simulate` and in the grade and per-vision report.
+    pub luma: Luma,
+    /// The second configuration for `compare`; defaults to `preset`.
+    pub compare_preset: Option<Preset>,
+    /// Weight overrides for the second configuration of `compare`.
+    pub compare_weights_file: Option<String>,
+    /// How many seeds `stability` optimizes from.
+    pub stability_seeds: usize,
+    /// Hex colors given after `simulate` or `grayscale`.
+    pub colors: Vec<Color>,
+}
+
+#[derive(Copy, Clone)]
+pub enum Command {
+    /// Optimize the built-in palettes and print the results.
+    Optimize,
+    /// Show how the given colors look with each color vision deficiency.
+    Simulate,
+    /// Show the given colors, or the optimized palettes if none are given,
+    /// as printed in grayscale, flagging pairs that become too similar.
+    Grayscale,
+    /// Print the cost and violations of a palette read as JSON from stdin,
+    /// failing if it breaks a hard constraint.
+    Cost,
+    /// Optimize the palettes, then drop their least distinct colors one at
+    /// a time until the rest meet every hard constraint.
+    Minimize,
+    /// Optimize the built-in palettes with two weight configurations from
+    /// the same seed, and compare the results term by term.
+    Compare,
+    /// Optimize the built-in palettes from several seeds and report how
+    /// far the final colors land from each other.
+    Stability,
+}
+
+/// How the optimizer splits up the color slots.
+#[derive(Copy, Clone)]
+pub enum Phases {
+    /// Perturb foreground and background colors in the same sweeps.
+    Joint,
+    /// Anneal the backgrounds with the foregrounds locked, then the
+    /// foregrounds with the backgrounds locked, so the two don't chase each
+    /// other.
+    Alternating,
+}
+
+impl Phases {
+    pub fn text(self) -> &'static str {
+        match self {
+            Phases::Joint => "joint",
+            Phases::Alternating => "alternating",
+        }
+    }
+}
+
+/// Which proposed moves the optimizer accepts. Moves that lower the cost
+/// are always taken.
+#[derive(Copy, Clone)]
+pub enum AcceptanceRule {
+    /// Take an uphill move of `delta` with probability `exp(-delta / T)`.
+    Metropolis,
+    /// Take any uphill move smaller than the temperature. Deterministic
+    /// given the schedule, and sometimes converges faster.
+    Threshold,
+    /// Never take an uphill move; useful as a final local refinement.
+    Greedy,
+}
+
+impl AcceptanceRule {
+    pub fn text(self) -> &'static str {
+        match self {
+            AcceptanceRule::Metropolis => "metropolis",
+            AcceptanceRule::Threshold => "threshold",
+            AcceptanceRule::Greedy => "greedy",
+        }
+    }
+}
+
+/// How the pairwise distances of a group of colors become a cost.
+#[derive(Copy, Clone, PartialEq)]
+pub enum DistanceObjective {
+    /// Root mean square of the shortfalls from the ideal distance. A good
+    /// average can hide one cramped pair.
+    Rms,
+    /// The worst (importance-weighted) shortfall, so only the closest pair
+    /// counts: maximizes the minimum distance.
+    Maximin,
+}
+
+impl DistanceObjective {
+    pub fn text(self) -> &'static str {
+        match self {
+            DistanceObjective::Rms => "rms",
+            DistanceObjective::Maximin => "maximin",
+        }
+    }
+}
+
+/// Post-optimization ordering of the foreground colors.
+#[derive(Copy, Clone)]
+pub enum SortOutput {
+    /// Keep the input order.
+    None,
+    /// Stable sort by LCH hue; the report maps input to output positions.
+    Hue,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// Only the final report.
+    Quiet,
+    /// Contrast tables before and after optimization, then the report.
+    Normal,
+    /// Additionally logs the cost periodically while annealing.
+    Verbose,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Format {
+    Table,
+    /// One line per mode with the costs, time and worst contrast.
+    Summary,
+    /// One document with the start/final palettes and costs of each mode.
+    Json,
+    /// A Base16 scheme YAML per mode.
+    Base16,
+    /// A Base24 scheme YAML per mode.
+    Base24,
+    /// An Adobe Swatch Exchange file with a group of foreground colors
+    /// per mode.
+    Ase,
+    /// The final fg ↔ bg contrast of each mode as nested JSON keyed by hex
+    /// code.
+    ContrastJson,
+}
+
+impl Format {
+    pub fn text(self) -> &'static str {
+        match self {
+            Format::Table => "table",
+            Format::Summary => "summary",
+            Format::Json => "json",
+            Format::Base16 => "base16",
+            Format::Base24 => "base24",
+            Format::Ase => "ase",
+            Format::ContrastJson => "contrast-json",
+        }
+    }
+
+    /// The file extension for reports in this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Table | Format::Summary => "txt",
+            Format::Json => "json",
+            Format::Base16 | Format::Base24 => "yaml",
+            Format::Ase => "ase",
+            Format::ContrastJson => "contrast.json",
+        }
+    }
+
+    /// Whether this format is meant for reading in a terminal.
+    pub fn is_text(self) -> bool {
+        matches!(self, Format::Table | Format::Summary)
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            command: Command::Optimize,
+            seed: None,
+            seed_bytes: None,
+            budget: None,
+            formats: vec![Format::Table],
+            output: None,
+            verbosity: Verbosity::Normal,
+            progress: false,
+            shuffle: false,
+            protected_pairs: vec![],
+            restarts: 1,
+            threads: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            importance: vec![],
+            text_uses: vec![],
+            achromatic: vec![],
+            background_only: false,
+            keep_hue_order: false,
+            pin_chroma: false,
+            hue_band: None,
+            feasible_start: false,
+            sort_output: SortOutput::None,
+            color_preview: false,
+            vision_table: None,
+            wiggle: 0.05,
+            anneal_wiggle: false,
+            perturbation: Perturbation::Srgb,
+            max_iterations: None,
+            phases: Phases::Joint,
+            phase_rounds: 1,
+            acceptance_rule: AcceptanceRule::Metropolis,
+            base16_overrides: vec![],
+            add_color: false,
+            snap_to: vec![],
+            candidates: vec![],
+            generate: None,
+            contrast_floor: false,
+            dark_bg: None,
+            light_bg: None,
+            palette_file: None,
+            batch: None,
+            batch_dir: None,
+            targets_file: None,
+            contrast_level: ContrastLevel::default(),
+            contrast_sigmoid: ContrastSigmoid::default(),
+            color_space: ColorSpace::default(),
+            precision: Precision::default(),
+            preset: Preset::Balanced,
+            weights_file: None,
+            cvd_weights: CvdWeights::default(),
+            cvd_severity: 1.,
+            distance_objective: DistanceObjective::Rms,
+            no_cvd: false,
+            no_target: false,
+            luma: Luma::default(),
+            compare_preset: None,
+            compare_weights_file: None,
+            stability_seeds: 8,
+            colors: vec![],
+        }
+    }
+}
+
+impl Options {
+    pub fn parse() -> Result<Options> {
+        let mut args: Vec<String> = std::env::args().skip(1).collect();
+        // The config's flags go first, so that the command line overrides
+        // them.
+        if let Some(i) = args.iter().position(|arg| arg == "--config") {
+            let path = flag_value("--config", args.get(i + 1).cloned())?;
+            args.drain(i..i + 2);
+            let mut config_args = RunConfig::load(&path)?.into_args()?;
+            config_args.append(&mut args);
+            args = config_args;
+        }
+        Self::parse_from(args.into_iter())
+    }
+
+    fn parse_from(mut args: impl Iterator<Item = String>) -> Result<Options> {
+        let mut opts = Options::default();
+        let mut colors = vec![];
+        let mut mandatory_backgrounds = vec![];
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--budget" => {
+                    let secs: f32 = parse_number(&arg, args.next())?;
+                    if !secs.is_finite() || secs <= 0. {
+                        return Err(Error::Usage(
+                            "--budget must be a finite number of seconds above 0".into(),
+                        ));
+                    }
+                    opts.budget = Some(Duration::from_secs_f32(secs));
+                }
+                "--seed" => {
+                    let value = flag_value(&arg, args.next())?;
+                    opts.seed_bytes = Some(seed_from_hex(&value)?);
+                }
+                "--quiet" => opts.verbosity = Verbosity::Quiet,
+                "--verbose" => opts.verbosity = Verbosity::Verbose,
+                "--progress" => opts.progress = true,
+                "--shuffle" => opts.shuffle = true,
+                "--background-only" => opts.background_only = true,
+                "--keep-hue-order" => opts.keep_hue_order = true,
+                "--pin-chroma" => opts.pin_chroma = true,
+                "--feasible-start" => opts.feasible_start = true,
+                "--hue-band" => opts.hue_band = Some(flag_value(&arg, args.next())?.parse()?),
+                "--add-color" => opts.add_color = true,
+                "--color-preview" => opts.color_preview = true,
+                "--no-cvd" => opts.no_cvd = true,
+                "--no-target" => opts.no_target = true,
+                "--contrast-floor" => opts.contrast_floor = true,
+                "--generate" => {
+                    let n = parse_number(&arg, args.next())?;
+                    if n < 2 {
+                        return Err(Error::Usage("--generate needs at least 2 colors".into()));
+                    }
+                    opts.generate = Some(n);
+                }
+                "--candidates" => {
+                    let value = flag_value(&arg, args.next())?;
+                    let hexes: Vec<&str> = value.split(',').collect();
+                    opts.candidates.extend(colors_from_hex(&hexes)?);
+                }
+                "--dark-bg" => opts.dark_bg = Some(parse_color(&arg, args.next())?),
+                "--light-bg" => opts.light_bg = Some(parse_color(&arg, args.next())?),
+                "--palette" => opts.palette_file = Some(flag_value(&arg, args.next())?),
+                "--batch" => opts.batch = Some(flag_value(&arg, args.next())?),
+                "--batch-dir" => opts.batch_dir = Some(flag_value(&arg, args.next())?),
+                "--targets" => opts.targets_file = Some(flag_value(&arg, args.next())?),
+                "--snap-to" => {
+                    let value = flag_value(&arg, args.next())?;
+                    let hexes: Vec<&str> = value.split(',').collect();
+                    opts.snap_to.extend(colors_from_hex(&hexes)?);
+                }
+                "--protect" => {
+                    let value = flag_value(&arg, args.next())?;
+                    opts.protected_pairs.push(parse_protected_pair(&value)?);
+                }
+                "--achromatic" => {
+                    for role in flag_value(&arg, args.next())?.split(',') {
+                        let role: BgRole = role.parse()?;
+                        if !BackgroundColors::MODIFIABLE.contains(&role) {
+                            return Err(Error::Usage(format!(
+                                "--achromatic: the {} background isn't optimized",
+                                role.text()
+                            )));
+                        }
+                        opts.achromatic.push(role);
+                    }
+                }
+                "--backgrounds" => {
+                    mandatory_backgrounds.push(flag_value(&arg, args.next())?);
+                }
+                "--text-use" => {
+                    let value = flag_value(&arg, args.next())?;
+                    let (index, text_use) = value.split_once(':').ok_or_else(|| {
+                        Error::Usage(format!("--text-use expects FG_INDEX:USE, got {value}"))
+                    })?;
+                    opts.text_uses
+                        .push((parse_field("foreground index", index)?, text_use.parse()?));
+                }
+                "--importance" => {
+                    let value = flag_value(&arg, args.next())?;
+                    opts.importance.push(parse_importance(&value)?);
+                }
+                "--restarts" => opts.restarts = parse_number(&arg, args.next())?,
+                "--seeds" => {
+                    opts.stability_seeds = parse_number(&arg, args.next())?;
+                    if opts.stability_seeds < 2 {
+                        return Err(Error::Usage("--seeds needs at least 2 seeds".into()));
+                    }
+                }
+                "--threads" => {
+                    opts.threads = parse_number(&arg, args.next())?;
+                    if opts.threads == 0 {
+                        return Err(Error::Usage("--threads must be positive".into()));
+                    }
+                }
+                "--sort-output" => {
+                    opts.sort_output = match flag_value(&arg, args.next())?.as_str() {
+                        "none" => SortOutput::None,
+                        "hue" => SortOutput::Hue,
+                        other => return Err(Error::unknown("sort order", other)),
+                    };
+                }
+                "--vision-table" => {
+                    opts.vision_table = Some(flag_value(&arg, args.next())?.parse()?);
+                }
+                "--wiggle" => {
+                    opts.wiggle = parse_number(&arg, args.next())?;
+                    if !(opts.wiggle > 0. && opts.wiggle <= 1.) {
+                        return Err(Error::Usage("--wiggle must be in (0, 1]".into()));
+                    }
+                }
+                "--anneal-wiggle" => opts.anneal_wiggle = true,
+                "--perturb" => opts.perturbation = flag_value(&arg, args.next())?.parse()?,
+                "--iterations" => opts.max_iterations = Some(parse_number(&arg, args.next())?),
+                "--color-space" => opts.color_space = flag_value(&arg, args.next())?.parse()?,
+                "--precision" => opts.precision = flag_value(&arg, args.next())?.parse()?,
+                "--contrast-steepness" | "--contrast-center" => {
+                    let x: f32 = parse_number(&arg, args.next())?;
+                    if !x.is_finite() || x < 0. {
+                        return Err(Error::Usage(format!(
+                            "{arg} must be a finite number of at least 0"
+                        )));
+                    }
+                    match arg.as_str() {
+                        "--contrast-steepness" => opts.contrast_sigmoid.steepness = x,
+                        _ => opts.contrast_sigmoid.center = x,
+                    }
+                }
+                "--contrast-level" => {
+                    opts.contrast_level = flag_value(&arg, args.next())?.parse()?
+                }
+                "--phases" => {
+                    let value = flag_value(&arg, args.next())?;
+                    opts.phases = [Phases::Joint, Phases::Alternating]
+                        .into_iter()
+                        .find(|p| p.text() == value)
+                        .ok_or_else(|| Error::unknown("phase mode", &value))?;
+                }
+                "--acceptance" => {
+                    let value = flag_value(&arg, args.next())?;
+                    opts.acceptance_rule = [
+                        AcceptanceRule::Metropolis,
+                        AcceptanceRule::Threshold,
+                        AcceptanceRule::Greedy,
+                    ]
+                    .into_iter()
+                    .find(|rule| rule.text() == value)
+                    .ok_or_else(|| Error::unknown("acceptance rule", &value))?;
+                }
+                "--phase-rounds" => {
+                    opts.phase_rounds = parse_number(&arg, args.next())?;
+                    if opts.phase_rounds == 0 {
+                        return Err(Error::Usage("--phase-rounds must be positive".into()));
+                    }
+                }
+                "--preset" => opts.preset = flag_value(&arg, args.next())?.parse()?,
+                "--weights" => opts.weights_file = Some(flag_value(&arg, args.next())?),
+                "--cvd-weights" => opts.cvd_weights = flag_value(&arg, args.next())?.parse()?,
+                "--cvd-severity" => {
+                    opts.cvd_severity = parse_number(&arg, args.next())?;
+                    if !(0. ..=1.).contains(&opts.cvd_severity) {
+                        return Err(Error::Usage("--cvd-severity must be in [0, 1]".into()));
+                    }
+                }
+                "--distance-objective" => {
+                    let value = flag_value(&arg, args.next())?;
+                    opts.distance_objective = [DistanceObjective::Rms, DistanceObjective::Maximin]
+                        .into_iter()
+                        .find(|o| o.text() == value)
+                        .ok_or_else(|| Error::unknown("distance objective", &value))?;
+                }
+                "--vs-preset" => {
+                    opts.compare_preset = Some(flag_value(&arg, args.next())?.parse()?)
+                }
+                "--vs-weights" => opts.compare_weights_file = Some(flag_value(&arg, args.next())?),
+                "--output" => opts.output = Some(flag_value(&arg, args.next())?),
+                "--luma" => opts.luma = flag_value(&arg, args.next())?.parse()?,
+                "--base16-map" => {
+                    let value = flag_value(&arg, args.next())?;
+                    opts.base16_overrides.push(base16::parse_override(&value)?);
+                }
+                "--format" => {
+                    opts.formats = vec![];
+                    for name in flag_value(&arg, args.next())?.split(',') {
+                        let format = [
+                            Format::Table,
+                            Format::Summary,
+                            Format::Json,
+                            Format::Base16,
+                            Format::Base24,
+                            Format::Ase,
+                            Format::ContrastJson,
+                        ]
+                        .into_iter()
+                        .find(|f| f.text() == name)
+                        .ok_or_else(|| Error::unknown("format", name))?;
+                        if opts.formats.contains(&format) {
+                            return Err(Error::Usage(format!("--format lists {name} twice")));
+                        }
+                        opts.formats.push(format);
+                    }
+                }
+                _ if arg.starts_with("--") => return Err(Error::unknown("flag", &arg)),
+                "simulate" => opts.command = Command::Simulate,
+                "grayscale" => opts.command = Command::Grayscale,
+                "compare" => opts.command = Command::Compare,
+                "stability" => opts.command = Command::Stability,
+                "minimize" => opts.command = Command::Minimize,
+                "cost" => opts.command = Command::Cost,
+                _ if matches!(opts.command, Command::Simulate | Command::Grayscale) => {
+                    colors.push(arg)
+                }
+                _ => {
+                    if opts.seed.is_some() {
+                        return Err(Error::Usage(format!("Unexpected argument {arg}")));
+                    }
+                    opts.seed = Some(arg);
+                }
+            }
+        }
+        // Parsed last, so that the minimum follows `--contrast-level` no
+        // matter where it appears.
+        for value in mandatory_backgrounds {
+            let pairs = parse_mandatory_backgrounds(&value, opts.contrast_level)?;
+            opts.protected_pairs.extend(pairs);
+        }
+        let colors: Vec<&str> = colors.iter().map(String::as_str).collect();
+        opts.colors = colors_from_hex(&colors)?;
+        if opts.generate.is_some() && opts.palette_file.is_some() {
+            return Err(Error::Usage(
+                "Pass either --generate or --palette, not both".into(),
+            ));
+        }
+        if opts.batch.is_some()
+            && (opts.palette_file.is_some() || opts.generate.is_some() || opts.output.is_some())
+        {
+            return Err(Error::Usage(
+                "--batch picks the palettes and output files; drop --palette, --generate and \
+                 --output"
+                    .into(),
+            ));
+        }
+        if opts.formats.len() > 1 {
+            if opts.batch.is_some() {
+                return Err(Error::Usage("--batch takes a single --format".into()));
+            }
+            let files: Vec<Format> = opts
+                .formats
+                .iter()
+                .copied()
+                .filter(|f| !f.is_text())
+                .collect();
+            if !files.is_empty() && opts.output.is_none() {
+                return Err(Error::Usage(
+                    "Several formats need --output FILE to name the files they write".into(),
+                ));
+            }
+            for (i, a) in files.iter().enumerate() {
+                if let Some(b) = files[i + 1..]
+                    .iter()
+                    .find(|b| b.extension() == a.extension())
+                {
+                    return Err(Error::Usage(format!(
+                        "--format {} and {} would both write a .{} file",
+                        a.text(),
+                        b.text(),
+                        a.extension()
+                    )));
+                }
+            }
+        }
+        if opts.batch_dir.is_some() && opts.batch.is_none() {
+            return Err(Error::Usage("--batch-dir needs --batch".into()));
+        }
+        if opts.seed.is_some() && opts.seed_bytes.is_some() {
+            return Err(Error::Usage(
+                "Pass either a SEED string or --seed, not both".into(),
+            ));
+        }
+        Ok(opts)
+    }
+
+    /// Where the report in `format` goes, with `None` for stdout. A single
+    /// format goes to `--output`. With several, the table and summary are
+    /// printed and every other format goes to `--output` with its own
+    /// extension, e.g. `palette.json` and `palette.ase` from
+    /// `--format table,json,ase --output palette`.
+    pub fn format_output(&self, format: Format) -> Option<String> {
+        if self.formats.len() == 1 {
+            return self.output.clone();
+        }
+        if format.is_text() {
+            return None;
+        }
+        let path = std::path::Path::new(self.output.as_ref()?);
+        Some(
+            path.with_extension(format.extension())
+                .to_string_lossy()
+                .into_owned(),
+        )
+    }
+
+    pub fn shows(&self, level: Verbosity) -> bool {
+        self.verbosity >= level
+    }
+}
+
+fn flag_value(flag: &str, value: Option<String>) -> Result<String> {
+    value.ok_or_else(|| Error::Usage(format!("{flag} expects a value")))
+}
+
+/// Parses the value of a numeric flag.
+fn parse_number<T: FromStr>(flag: &str, value: Option<String>) -> Result<T> {
+    let value = flag_value(flag, value)?;
+    value
+        .parse()
+        .map_err(|_| Error::Usage(format!("{flag} expects a number, got {value}")))
+}
+
+/// Parses one `:`-separated field of a flag value.
+fn parse_field<T: FromStr>(what: &str, s: &str) -> Result<T> {
+    s.parse()
+        .map_err(|_| Error::Usage(format!("Invalid {what} {s}")))
+}
+
+fn parse_color(flag: &str, value: Option<String>) -> Result<Color> {
+    let value = flag_value(flag, value)?;
+    Ok(colors_from_hex(&[&value])?[0])
+}
+
+fn parse_protected_pair(s: &str) -> Result<ProtectedPair> {
+    let parts: Vec<_> = s.split(':').collect();
+    if parts.len() != 3 {
+        return Err(Error::Usage(format!(
+            "--protect expects FG_INDEX:BG_ROLE:MIN_RATIO, got {s}"
+        )));
+    }
+    let min_ratio: f32 = parse_field("contrast ratio", parts[2])?;
+    // Contrast ratios run from 1:1 to 21:1, and a NaN minimum could never
+    // be violated.
+    if !(1. ..=21.).contains(&min_ratio) {
+        return Err(Error::Usage(format!(
+            "--protect ratio must be between 1 and 21, got {}",
+            parts[2]
+        )));
+    }
+    Ok(ProtectedPair {
+        fg_index: parse_field("foreground index", parts[0])?,
+        bg_role: parts[1].parse()?,
+        min_ratio,
+    })
+}
+
+/// Parses `FG_INDEX:BG_ROLE[,BG_ROLE...]` into one protected pair per
+/// background, each requiring the text contrast minimum at `level`.
+fn parse_mandatory_backgrounds(s: &str, level: ContrastLevel) -> Result<Vec<ProtectedPair>> {
+    let (index, roles) = s.split_once(':').ok_or_else(|| {
+        Error::Usage(format!(
+            "--backgrounds expects FG_INDEX:BG_ROLE[,BG_ROLE...], got {s}"
+        ))
+    })?;
+    let fg_index = parse_field("foreground index", index)?;
+    roles
+        .split(',')
+        .map(|role| {
+            Ok(ProtectedPair {
+                fg_index,
+                bg_role: role.parse()?,
+                min_ratio: required_ratio(ContrastNeed::Text, level),
+            })
+        })
+        .collect()
+}
+
+fn parse_importance(s: &str) -> Result<(usize, f32)> {
+    let (index, weight) = s
+        .split_once(':')
+        .ok_or_else(|| Error::Usage(format!("--importance expects FG_INDEX:WEIGHT, got {s}")))?;
+    let weight: f32 = parse_field("importance weight", weight)?;
+    if !(weight.is_finite() && weight >= 0.) {
+        return Err(Error::Usage(
+            "importance weights must be finite and non-negative".into(),
+        ));
+    }
+    Ok((parse_field("foreground index", index)?, weight))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options> {
+        Options::parse_from(args.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn budget_must_be_finite_and_positive() {
+        for secs in ["-1", "0", "nan", "inf"] {
+            let result = parse(&["--budget", secs, "seed1"]);
+            assert!(matches!(result, Err(Error::Usage(_))), "--budget {secs}");
+        }
+        let opts = parse(&["--budget", "1.5", "seed1"]).unwrap();
+        assert_eq!(opts.budget, Some(Duration::from_millis(1500)));
+    }
+
+    #[test]
+    fn importance_must_be_finite_and_non_negative() {
+        for weight in ["-1", "nan", "inf"] {
+            let importance = format!("0:{weight}");
+            let result = parse(&["--importance", &importance, "seed1"]);
+            assert!(
+                matches!(result, Err(Error::Usage(_))),
+                "--importance {importance}"
+            );
+        }
+        let opts = parse(&["--importance", "0:0", "--importance", "1:4", "seed1"]).unwrap();
+        assert_eq!(opts.importance, [(0, 0.), (1, 4.)]);
+    }
+
+    #[test]
+    fn protected_ratio_must_be_a_contrast_ratio() {
+        for ratio in ["nan", "-3", "0.5", "22", "inf"] {
+            let pair = format!("0:main:{ratio}");
+            let result = parse(&["--protect", &pair, "seed1"]);
+            assert!(matches!(result, Err(Error::Usage(_))), "--protect {pair}");
+        }
+        let opts = parse(&["--protect", "0:main:4.5", "seed1"]).unwrap();
+        assert_eq!(opts.protected_pairs[0].min_ratio, 4.5);
+    }
+}
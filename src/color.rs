@@ -1,66 +1,321 @@
-use std::{cmp::Ordering, fmt::Display, str::FromStr};
+use std::{fmt::Display, str::FromStr};
 
-use p::{convert::FromColorUnclamped, ColorDifference, Lch, RelativeContrast};
+use p::{convert::FromColorUnclamped, ColorDifference, FromColor, Lch, RelativeContrast};
 use palette as p;
 use rand::Rng as RngTrait;
 
 use crate::{
+    brettel::distance_under_vision,
     convert::{array_to_triple, triple_to_array},
-    cost::{ContrastNeed, ScaledCost},
-    random::Rng,
+    cost::{
+        required_ratio, success_criterion, ContrastLevel, ContrastNeed, ContrastSigmoid,
+        ScaledCost, SuccessCriterion,
+    },
+    error::{Error, Result},
+    math::cmp_nan_last,
+    okhsl::Okhsl,
 };
 
 pub type Color = p::rgb::Rgb<p::encoding::srgb::Srgb, f32>;
 pub type LinearRgb = p::rgb::Rgb<p::encoding::Linear<p::encoding::srgb::Srgb>, f32>;
 
+/// The RGB space whose device values a [`Color`] holds. Both spaces share
+/// the sRGB transfer function and white point; Display P3 has wider
+/// primaries, so the same hex looks more saturated on a P3 display.
+///
+/// Perceptual conversions (CIELCh, luminance, CVD simulation) first map
+/// colors to their [`srgb_appearance`], so the optimizer sees them as they
+/// look on a display of the working space. Input and output hex values
+/// stay in the working space.
+#[derive(Copy, Clone, Debug, Default)]
+pub enum ColorSpace {
+    #[default]
+    Srgb,
+    DisplayP3,
+}
+
+impl ColorSpace {
+    pub fn text(self) -> &'static str {
+        match self {
+            ColorSpace::Srgb => "srgb",
+            ColorSpace::DisplayP3 => "display-p3",
+        }
+    }
+}
+
+impl FromStr for ColorSpace {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "srgb" => Ok(ColorSpace::Srgb),
+            "display-p3" => Ok(ColorSpace::DisplayP3),
+            _ => Err(Error::unknown("color space", s)),
+        }
+    }
+}
+
+/// Linear Display P3 to linear sRGB, both relative to D65.
+const P3_TO_SRGB: [f32; 9] = [
+    1.2249402, -0.2249402, 0., -0.042057, 1.042057, 0., -0.0196376, -0.078636, 1.0982736,
+];
+/// The inverse of [`P3_TO_SRGB`].
+const SRGB_TO_P3: [f32; 9] = [
+    0.822462, 0.177538, 0., 0.0331942, 0.9668058, 0., 0.0170826, 0.0723974, 0.9105199,
+];
+
+/// Applies a linear-light RGB matrix to `c`'s channels.
+fn transform_linear(c: Color, m: &[f32; 9]) -> Color {
+    let [r, g, b] = triple_to_array(LinearRgb::from_encoding(c).into_components());
+    Color::from_encoding(LinearRgb::new(
+        m[0] * r + m[1] * g + m[2] * b,
+        m[3] * r + m[4] * g + m[5] * b,
+        m[6] * r + m[7] * g + m[8] * b,
+    ))
+}
+
+/// The sRGB color that looks like `c` does in `space`. Wide
+/// gamut colors map to channels outside 0..=1, which the conversions to
+/// other spaces handle fine.
+pub fn srgb_appearance(c: Color, space: ColorSpace) -> Color {
+    match space {
+        ColorSpace::Srgb => c,
+        ColorSpace::DisplayP3 => transform_linear(c, &P3_TO_SRGB),
+    }
+}
+
+/// The inverse of [`srgb_appearance`].
+pub fn from_srgb_appearance(c: Color, space: ColorSpace) -> Color {
+    match space {
+        ColorSpace::Srgb => c,
+        ColorSpace::DisplayP3 => transform_linear(c, &SRGB_TO_P3),
+    }
+}
+
 #[track_caller]
 pub fn rgb(s: &'static str) -> Color {
     let c = p::rgb::Rgb::<p::encoding::srgb::Srgb, u8>::from_str(s).expect("invalid rgb color");
     Color::from_format(c)
 }
 
+/// Parses a `#rrggbb` (or `rrggbb`) color given at runtime.
+pub fn parse_rgb(s: &str) -> Result<Color> {
+    let c =
+        p::rgb::Rgb::<p::encoding::srgb::Srgb, u8>::from_str(s).map_err(|_| Error::ParseColor {
+            input: s.to_string(),
+        })?;
+    Ok(Color::from_format(c))
+}
+
+/// Parses a list of runtime hex strings, e.g. a user-supplied palette.
+pub fn colors_from_hex(strs: &[&str]) -> Result<Vec<Color>> {
+    strs.iter().map(|s| parse_rgb(s)).collect()
+}
+
+/// Whether every channel of `a` and `b` differs by at most `eps`.
+#[cfg(test)]
+pub fn colors_approx_eq(a: Color, b: Color, eps: f32) -> bool {
+    (a.red - b.red).abs() <= eps
+        && (a.green - b.green).abs() <= eps
+        && (a.blue - b.blue).abs() <= eps
+}
+
+/// `c` quantized to 8 bits per channel as `0xRRGGBB`, so colors that print
+/// as the same hex also hash and compare the same.
+pub fn color_key(c: Color) -> u32 {
+    let c = c.into_format::<u8>();
+    u32::from_be_bytes([0, c.red, c.green, c.blue])
+}
+
+pub fn to_lch(c: Color, space: ColorSpace) -> Lch {
+    Lch::from_color_unclamped(srgb_appearance(c, space))
+}
+
+/// `c` with its LCH chroma reduced to at most `max_chroma`, keeping its
+/// lightness and hue.
+pub fn limit_chroma(c: Color, max_chroma: f32, space: ColorSpace) -> Color {
+    let lch = to_lch(c, space);
+    if lch.chroma <= max_chroma {
+        return c;
+    }
+    from_lch(Lch::new(lch.l, max_chroma, lch.hue), space)
+}
+
+/// A warm or cool palette intent, approximating low and high correlated
+/// color temperature by a band of LCH hues.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum HueBand {
+    /// Magentas through reds and oranges to yellows.
+    Warm,
+    /// Greens through cyans and blues to violets.
+    Cool,
+}
+
+impl HueBand {
+    /// How far inside the band [`HueBand::pull_into`] puts a hue, so that
+    /// the gamut clamp can't nudge it back out.
+    const MARGIN: f32 = 2.;
+
+    pub fn text(self) -> &'static str {
+        match self {
+            HueBand::Warm => "warm",
+            HueBand::Cool => "cool",
+        }
+    }
+
+    /// First and last hue of the band in degrees, going up; the warm band
+    /// wraps through 0.
+    fn degrees(self) -> (f32, f32) {
+        match self {
+            HueBand::Warm => (330., 100.),
+            HueBand::Cool => (140., 310.),
+        }
+    }
+
+    /// Whether the LCH hue `hue` (in degrees) lies in the band.
+    pub fn contains(self, hue: f32) -> bool {
+        let (first, last) = self.degrees();
+        (hue - first).rem_euclid(360.) <= (last - first).rem_euclid(360.)
+    }
+
+    /// `c` with its hue moved just inside the nearer edge of the band,
+    /// keeping its lightness and as much of its chroma as the gamut allows
+    /// at the new hue. Colors already in the band are returned as is.
+    pub fn pull_into(self, c: Color, space: ColorSpace) -> Color {
+        let lch = to_lch(c, space);
+        let hue = lch.hue.to_positive_degrees();
+        if self.contains(hue) {
+            return c;
+        }
+        let (first, last) = self.degrees();
+        let hue = if (hue - last).rem_euclid(360.) < (first - hue).rem_euclid(360.) {
+            last - Self::MARGIN
+        } else {
+            first + Self::MARGIN
+        };
+        let max_chroma = gamut_headroom(from_lch(Lch::new(lch.l, 0., hue), space), space);
+        from_lch(Lch::new(lch.l, lch.chroma.min(max_chroma), hue), space)
+    }
+}
+
+impl FromStr for HueBand {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "warm" => Ok(HueBand::Warm),
+            "cool" => Ok(HueBand::Cool),
+            _ => Err(Error::unknown("hue band", s)),
+        }
+    }
+}
+
+/// How much LCH chroma could be added to `c`, at its lightness and hue,
+/// before it leaves the gamut. Colors with little headroom get clipped by
+/// most moves that raise their chroma.
+pub fn gamut_headroom(c: Color, space: ColorSpace) -> f32 {
+    let lch = to_lch(c, space);
+    let in_gamut = |chroma: f32| {
+        let c = from_srgb_appearance(
+            Color::from_color_unclamped(Lch::new(lch.l, chroma, lch.hue)),
+            space,
+        );
+        [c.red, c.green, c.blue]
+            .iter()
+            .all(|x| (-1e-4..=1. + 1e-4).contains(x))
+    };
+    if !in_gamut(lch.chroma) {
+        return 0.;
+    }
+    // Along a line of constant lightness and hue the gamut is one interval
+    // of chroma starting at gray, so bisect for its end.
+    let (mut lo, mut hi) = (lch.chroma, lch.chroma + 200.);
+    for _ in 0..24 {
+        let mid = (lo + hi) / 2.;
+        if in_gamut(mid) {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    lo - lch.chroma
+}
+
+/// The inverse of [`to_lch`], clamped to the gamut.
+pub fn from_lch(lch: Lch, space: ColorSpace) -> Color {
+    clamp_to_gamut(from_srgb_appearance(
+        Color::from_color_unclamped(lch),
+        space,
+    ))
+}
+
 // Checked that this is close to JS
-pub fn distance(c1: Color, c2: Color) -> f32 {
-    let c1 = Lch::from_color_unclamped(c1);
-    let c2 = Lch::from_color_unclamped(c2);
+pub fn distance(c1: Color, c2: Color, space: ColorSpace) -> f32 {
+    distance_lch(&to_lch(c1, space), &to_lch(c2, space))
+}
+
+/// Each color of `before` with the color at the same index of `after`,
+/// and how far it moved.
+pub fn palette_diff(
+    before: &[Color],
+    after: &[Color],
+    space: ColorSpace,
+) -> Vec<(Color, Color, f32)> {
+    assert_eq!(before.len(), after.len());
+    before
+        .iter()
+        .zip(after)
+        .map(|(&c1, &c2)| (c1, c2, distance(c1, c2, space)))
+        .collect()
+}
+
+/// Like [`distance`], for colors that have already been converted.
+pub fn distance_lch(c1: &Lch, c2: &Lch) -> f32 {
     // Note: This color difference is different from the one used by chroma.js
     // This uses CIEDE2000 whereas chroma.js used the older CMC l:c (1984)
-    c1.get_color_difference(&c2)
+    c1.get_color_difference(c2)
 }
 
-pub fn get_closest_color(c: Color, cs: &[Color]) -> Color {
-    assert!(cs.len() > 0);
+/// The color in `cs` closest to `c`, or `None` if `cs` is empty.
+pub fn get_closest_color(c: Color, cs: &[Color], space: ColorSpace) -> Option<Color> {
     let mut out = None;
-    let mut closest = 1e10;
+    let mut closest = f32::INFINITY;
     for x in cs.iter() {
-        let d = distance(c, *x);
+        let d = distance(c, *x, space);
         if d < closest {
             closest = d;
             out = Some(*x);
         }
     }
-    out.unwrap()
+    out
 }
 
-pub fn pairwise_distances_2(bg_colors: &[Color], fg_colors: &[Color], out: &mut Vec<f32>) {
+pub fn pairwise_distances_2(bg_colors: &[Lch], fg_colors: &[Lch], out: &mut Vec<f32>) {
     out.clear();
     for bg_color in bg_colors {
         for fg_color in fg_colors {
-            out.push(distance(*bg_color, *fg_color));
+            out.push(distance_lch(bg_color, fg_color));
         }
     }
 }
 
-pub fn pairwise_distances(fg_colors: &[Color], out: &mut Vec<f32>) {
+pub fn pairwise_distances(fg_colors: &[Lch], out: &mut Vec<f32>) {
     out.clear();
     for i in 0..fg_colors.len() {
         for j in (i + 1)..fg_colors.len() {
-            out.push(distance(fg_colors[i], fg_colors[j]));
+            out.push(distance_lch(&fg_colors[i], &fg_colors[j]));
         }
     }
 }
 
-pub fn random_nearby_color(c: Color, rng: &mut Rng) -> Color {
+/// A uniformly random color.
+pub fn random_color(rng: &mut (impl RngTrait + ?Sized)) -> Color {
+    Color::new(rng.gen(), rng.gen(), rng.gen())
+}
+
+/// Moves one random sRGB channel of `c` by up to `wiggle` in either
+/// direction. The result may fall outside the sRGB gamut.
+pub fn random_nearby_color(c: Color, wiggle: f32, rng: &mut (impl RngTrait + ?Sized)) -> Color {
     let channel = rng.gen_range(0..3);
     // NOTE: The original code in category-colors uses chroma.js's
     // chroma.Color's .gl() method which is documented to return CMYK.
@@ -70,14 +325,135 @@ pub fn random_nearby_color(c: Color, rng: &mut Rng) -> Color {
     let mut rgb = triple_to_array(c.into_components());
     let old_val = rgb[channel];
 
-    const WIGGLE: f32 = 0.05;
-    let new_val = f32::clamp(old_val + rng.gen_range(-WIGGLE..=WIGGLE), 0., 1.);
+    rgb[channel] = old_val + rng.gen_range(-wiggle..=wiggle);
+    Color::from_components(array_to_triple(rgb))
+}
+
+/// Moves one random OKHSL component of `c` by up to `wiggle` in either
+/// direction. Hue (in turns) wraps around; the result may fall outside the
+/// sRGB gamut.
+pub fn random_nearby_color_okhsl(
+    c: Color,
+    wiggle: f32,
+    rng: &mut (impl RngTrait + ?Sized),
+) -> Color {
+    let mut hsl = Okhsl::from_srgb(c);
+    let delta = rng.gen_range(-wiggle..=wiggle);
+    match rng.gen_range(0..3) {
+        0 => hsl.h = (hsl.h + delta).rem_euclid(1.),
+        1 => hsl.s = f32::clamp(hsl.s + delta, 0., 1.),
+        _ => hsl.l = f32::clamp(hsl.l + delta, 0., 1.),
+    }
+    hsl.to_srgb()
+}
 
-    rgb[channel] = new_val;
+/// Moves the LCH lightness or hue of `c` by up to `wiggle` of its range
+/// (100 and 360° respectively), keeping its chroma. The result may fall
+/// outside the sRGB gamut.
+pub fn random_nearby_color_fixed_chroma(
+    c: Color,
+    wiggle: f32,
+    space: ColorSpace,
+    rng: &mut (impl RngTrait + ?Sized),
+) -> Color {
+    let mut lch = to_lch(c, space);
+    let delta = rng.gen_range(-wiggle..=wiggle);
+    if rng.gen_bool(0.5) {
+        lch.l = f32::clamp(lch.l + 100. * delta, 0., 100.);
+    } else {
+        lch.hue += 360. * delta;
+    }
+    from_srgb_appearance(Color::from_color_unclamped(lch), space)
+}
+
+/// Clamps each channel of `c` into the sRGB gamut.
+pub fn clamp_to_gamut(c: Color) -> Color {
+    let rgb = triple_to_array(c.into_components()).map(|x| f32::clamp(x, 0., 1.));
     Color::from_components(array_to_triple(rgb))
 }
 
-#[allow(dead_code)]
+/// Euclidean distance in sRGB between a color and its clamped version,
+/// i.e. how far clamping moved it.
+pub fn clip_delta(pre: Color, post: Color) -> f32 {
+    let pre = triple_to_array(pre.into_components());
+    let post = triple_to_array(post.into_components());
+    pre.iter()
+        .zip(post)
+        .map(|(a, b)| (a - b).powi(2))
+        .sum::<f32>()
+        .sqrt()
+}
+
+/// One of the `neighbors` colors of `candidates` closest to `c` (other
+/// than the one `c` prints as), picked uniformly. For optimizing over a fixed set of
+/// approved colors rather than continuously.
+pub fn random_nearby_candidate(
+    c: Color,
+    candidates: &[Color],
+    neighbors: usize,
+    space: ColorSpace,
+    rng: &mut (impl RngTrait + ?Sized),
+) -> Color {
+    let mut nearest: Vec<(f32, Color)> = candidates
+        .iter()
+        .map(|&k| (distance(c, k, space), k))
+        .filter(|&(_, k)| color_key(k) != color_key(c))
+        .collect();
+    if nearest.is_empty() {
+        return c;
+    }
+    nearest.sort_by(|(d1, _), (d2, _)| cmp_nan_last(*d1, *d2));
+    nearest.truncate(neighbors.max(1));
+    nearest[rng.gen_range(0..nearest.len())].1
+}
+
+/// The color space in which the optimizer proposes moves.
+#[derive(Copy, Clone, Debug)]
+pub enum Perturbation {
+    /// One sRGB channel at a time; see [`random_nearby_color`].
+    Srgb,
+    /// One of OKHSL hue, saturation or lightness at a time, so a step of a
+    /// given size looks about as large for every hue.
+    Okhsl,
+}
+
+impl Perturbation {
+    pub fn text(self) -> &'static str {
+        match self {
+            Perturbation::Srgb => "srgb",
+            Perturbation::Okhsl => "okhsl",
+        }
+    }
+
+    /// Proposes a color near `c`, clamped to sRGB. Also returns how far the
+    /// clamp moved it (see [`clip_delta`]).
+    pub fn nearby_color(
+        self,
+        c: Color,
+        wiggle: f32,
+        rng: &mut (impl RngTrait + ?Sized),
+    ) -> (Color, f32) {
+        let pre = match self {
+            Perturbation::Srgb => random_nearby_color(c, wiggle, rng),
+            Perturbation::Okhsl => random_nearby_color_okhsl(c, wiggle, rng),
+        };
+        let post = clamp_to_gamut(pre);
+        (post, clip_delta(pre, post))
+    }
+}
+
+impl FromStr for Perturbation {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "srgb" => Ok(Perturbation::Srgb),
+            "okhsl" => Ok(Perturbation::Okhsl),
+            _ => Err(Error::unknown("perturbation space", s)),
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub enum Vision {
     Default,
@@ -91,6 +467,60 @@ pub enum Vision {
     Achromatomaly,
 }
 
+impl Vision {
+    /// Every vision type, starting with `Default`.
+    pub fn all() -> impl Iterator<Item = Vision> {
+        use Vision::*;
+        [
+            Default,
+            Protanopia,
+            Protonomaly,
+            Deuteranopia,
+            Deuteranomaly,
+            Tritanopia,
+            Tritanomaly,
+            Achromatopsia,
+            Achromatomaly,
+        ]
+        .into_iter()
+    }
+
+    pub fn text(self) -> &'static str {
+        use Vision::*;
+        match self {
+            Default => "default",
+            Protanopia => "protanopia",
+            Protonomaly => "protonomaly",
+            Deuteranopia => "deuteranopia",
+            Deuteranomaly => "deuteranomaly",
+            Tritanopia => "tritanopia",
+            Tritanomaly => "tritanomaly",
+            Achromatopsia => "achromatopsia",
+            Achromatomaly => "achromatomaly",
+        }
+    }
+}
+
+impl FromStr for Vision {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        use Vision::*;
+        Ok(match s {
+            "default" => Default,
+            "protanopia" => Protanopia,
+            "protonomaly" => Protonomaly,
+            "deuteranopia" => Deuteranopia,
+            "deuteranomaly" => Deuteranomaly,
+            "tritanopia" => Tritanopia,
+            "tritanomaly" => Tritanomaly,
+            "achromatopsia" => Achromatopsia,
+            "achromatomaly" => Achromatomaly,
+            _ => return Err(Error::unknown("vision type", s)),
+        })
+    }
+}
+
 pub fn hex_colors(cs: &[Color]) -> Vec<String> {
     cs.iter()
         .map(|c| format!("#{:x}", c.into_format::<u8>()))
@@ -107,7 +537,9 @@ pub struct ColorDataTable<X> {
     info: &'static str,
 }
 
-enum Attention {
+#[allow(dead_code)]
+#[derive(Debug, PartialEq)]
+pub enum Attention {
     Good,
     Normal,
     Bad,
@@ -115,6 +547,12 @@ enum Attention {
 
 pub trait DrawAttention {
     fn attention(&self) -> Attention;
+
+    /// The success criterion a [`Attention::Bad`] value fails, for values
+    /// that stand for a WCAG requirement.
+    fn failed_criterion(&self) -> Option<SuccessCriterion> {
+        None
+    }
 }
 
 impl<X> ColorDataTable<X> {
@@ -141,15 +579,42 @@ impl<X> ColorDataTable<X> {
     }
 }
 
+impl ColorDataTable<ContrastRatio> {
+    /// The table as `{"#row": {"#col": {"ratio": 4.52, "passes": true,
+    /// "criterion": "1.4.3"}}}`, keyed by hex code. Ratios are rounded to
+    /// two decimals as in the printed table; `passes` is decided on the
+    /// exact value, against the success criterion in `criterion`.
+    pub fn to_json(&self) -> serde_json::Value {
+        let col_hexes = hex_colors(&self.cols);
+        let rows = hex_colors(&self.rows)
+            .into_iter()
+            .zip(&self.data)
+            .map(|(row, data)| {
+                let cells = col_hexes.iter().zip(data).map(|(col, ratio)| {
+                    let cell = serde_json::json!({
+                        "ratio": (f64::from(ratio.value()) * 100.).round() / 100.,
+                        "passes": ratio.attention() != Attention::Bad,
+                        "criterion": ratio.success_criterion().id,
+                    });
+                    (col.clone(), cell)
+                });
+                (row, serde_json::Value::Object(cells.collect()))
+            });
+        serde_json::Value::Object(rows.collect())
+    }
+}
+
 impl<X: Clone> ColorDataTable<X> {
-    pub fn sort_rows(&mut self, compare: &dyn Fn(&[X], &[X]) -> Ordering) {
-        let mut glued: Vec<_> = self
-            .rows
-            .clone()
+    /// Sorts the rows by ascending `key`, with NaN keys last.
+    pub fn sort_rows_by_key(&mut self, key: &dyn Fn(&[X]) -> f32) {
+        let keys: Vec<f32> = self.data.iter().map(|row| key(row)).collect();
+        let mut glued: Vec<_> = keys
             .into_iter()
-            .zip(self.data.clone().into_iter())
+            .zip(self.rows.clone())
+            .zip(self.data.clone())
             .collect();
-        glued.sort_by(|(_, v1), (_, v2)| compare(v1, v2));
+        glued.sort_by(|((k1, _), _), ((k2, _), _)| cmp_nan_last(*k1, *k2));
+        let glued = glued.into_iter().map(|((_, r), d)| (r, d));
         for (i, (r, d)) in glued.into_iter().enumerate() {
             self.rows[i] = r;
             self.data[i] = d;
@@ -161,15 +626,40 @@ impl<X: Display + DrawAttention> ColorDataTable<X> {
     pub fn table(&self) -> prettytable::Table {
         let mut t = Table::new();
         t.set_format(*prettytable::format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+        // Rows with a failing value name what it fails in a last column. A
+        // color paired with itself always fails, so those cells don't count.
+        let failed: Vec<Vec<String>> = self
+            .data
+            .iter()
+            .zip(&self.rows)
+            .map(|(row, row_color)| {
+                let mut ids: Vec<String> = vec![];
+                let others = row
+                    .iter()
+                    .zip(&self.cols)
+                    .filter(|(_, col_color)| color_key(**col_color) != color_key(*row_color));
+                for sc in others.filter_map(|(x, _)| x.failed_criterion()) {
+                    let id = format!("SC {}", sc.id);
+                    if !ids.contains(&id) {
+                        ids.push(id);
+                    }
+                }
+                ids
+            })
+            .collect();
+        let show_failed = failed.iter().any(|ids| !ids.is_empty());
         let mut headings = vec![self.info.to_string()];
         headings.extend(hex_colors(&self.cols));
+        if show_failed {
+            headings.push("fails".to_string());
+        }
         t.add_row(Row::new(
             headings
                 .into_iter()
                 .map(|s| {
                     let mut c = Cell::new(&s);
                     c.align(Alignment::CENTER);
-                    return c;
+                    c
                 })
                 .collect(),
         ));
@@ -188,9 +678,12 @@ impl<X: Display + DrawAttention> ColorDataTable<X> {
                 }
                 row.add_cell(c);
             }
+            if show_failed {
+                row.add_cell(Cell::new(&failed[i].join(", ")));
+            }
             t.add_row(row);
         }
-        return t;
+        t
     }
 }
 
@@ -204,45 +697,73 @@ impl<X: Display + DrawAttention> Display for ColorDataTable<X> {
 pub struct ContrastRatio {
     value: f32,
     need: ContrastNeed,
+    level: ContrastLevel,
 }
 
 impl ContrastRatio {
-    pub fn new(value: f32, need: ContrastNeed) -> ContrastRatio {
-        if value < 1.0 {
-            return ContrastRatio {
-                value: 1. / value,
-                need,
-            };
-        }
-        ContrastRatio { value, need }
+    pub fn new(value: f32, need: ContrastNeed, level: ContrastLevel) -> ContrastRatio {
+        // Contrast is symmetric, so new(r) and new(1/r) must agree.
+        // cost() relies on the normalized value lying in [1, 21].
+        let value = if value < 1.0 { 1. / value } else { value };
+        debug_assert!(
+            (1. ..=21.).contains(&value),
+            "contrast ratio {value} outside of [1, 21]"
+        );
+        ContrastRatio { value, need, level }
+    }
+    pub fn for_pair(
+        c1: Color,
+        c2: Color,
+        need: ContrastNeed,
+        level: ContrastLevel,
+        space: ColorSpace,
+    ) -> ContrastRatio {
+        let ratio = srgb_appearance(c1, space).get_contrast_ratio(&srgb_appearance(c2, space));
+        Self::new(ratio, need, level)
     }
-    pub fn for_pair(c1: Color, c2: Color, need: ContrastNeed) -> ContrastRatio {
-        Self::new(c1.get_contrast_ratio(&c2), need)
+    /// Like [`ContrastRatio::for_pair`], from the colors'
+    /// [`relative_luminance`]s.
+    pub fn from_luminances(
+        y1: f32,
+        y2: f32,
+        need: ContrastNeed,
+        level: ContrastLevel,
+    ) -> ContrastRatio {
+        Self::new(p::contrast_ratio(y1, y2), need, level)
     }
     pub fn value(&self) -> f32 {
         self.value
     }
-    pub fn need(&self) -> ContrastNeed {
-        self.need
+    pub fn required_ratio(&self) -> f32 {
+        required_ratio(self.need, self.level)
     }
-    pub fn cost(&self) -> ScaledCost {
+    /// Where [`ContrastRatio::required_ratio`] comes from.
+    pub fn success_criterion(&self) -> SuccessCriterion {
+        success_criterion(self.need, self.level)
+    }
+    pub fn cost(&self, sigmoid: ContrastSigmoid) -> ScaledCost {
         let ratio = self.value();
-        assert!(1. <= ratio && ratio <= 21.);
-        let min_ratio = self.need().minimum_ratio();
+        assert!((1. ..=21.).contains(&ratio));
+        let min_ratio = self.required_ratio();
         if ratio < min_ratio {
             return ScaledCost::new(100.);
         }
         // Sigmoid pushing towards high contrast
-        ScaledCost::new(100. / (1. + (4. * (self.value() - ratio)).exp()))
+        let margin = ratio - min_ratio - sigmoid.center;
+        ScaledCost::new(100. / (1. + (sigmoid.steepness * margin).exp()))
     }
 }
 
 impl DrawAttention for ContrastRatio {
     fn attention(&self) -> Attention {
-        if self.value() < self.need().minimum_ratio() {
+        if self.value() < self.required_ratio() {
             return Attention::Bad;
         }
-        return Attention::Normal;
+        Attention::Normal
+    }
+
+    fn failed_criterion(&self) -> Option<SuccessCriterion> {
+        (self.attention() == Attention::Bad).then(|| self.success_criterion())
     }
 }
 
@@ -252,12 +773,274 @@ impl Display for ContrastRatio {
     }
 }
 
+/// WCAG relative luminance, i.e. the Y of CIE XYZ.
+pub fn relative_luminance(c: Color, space: ColorSpace) -> f32 {
+    p::Xyz::from_color(srgb_appearance(c, space)).y
+}
+
+/// WCAG contrast of `c` against pure white and pure black, for a quick
+/// read on whether it works as text on either canvas.
+pub fn contrast_on_extremes(c: Color, space: ColorSpace) -> (f32, f32) {
+    let y = relative_luminance(c, space);
+    let ratio = |canvas: f32| {
+        ContrastRatio::from_luminances(y, canvas, ContrastNeed::Text, ContrastLevel::default())
+            .value()
+    };
+    (ratio(1.), ratio(0.))
+}
+
+/// Text color for a label drawn on a palette color.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TextColor {
+    Black,
+    White,
+}
+
+impl TextColor {
+    pub fn text(self) -> &'static str {
+        match self {
+            TextColor::Black => "black",
+            TextColor::White => "white",
+        }
+    }
+}
+
+/// Whether black or white text has the higher WCAG contrast on `bg`, e.g.
+/// for a tag filled with a category color, and that contrast. Ties go to
+/// black.
+pub fn best_text_color(
+    bg: Color,
+    level: ContrastLevel,
+    space: ColorSpace,
+) -> (TextColor, ContrastRatio) {
+    let on = |text: &'static str| {
+        ContrastRatio::for_pair(rgb(text), bg, ContrastNeed::Text, level, space)
+    };
+    let (black, white) = (on("#000000"), on("#ffffff"));
+    if white.value() > black.value() {
+        (TextColor::White, white)
+    } else {
+        (TextColor::Black, black)
+    }
+}
+
+/// The gray with the same Rec.709 luminance as `c`, i.e. how `c` prints
+/// in black and white.
+pub fn grayscale(c: Color, space: ColorSpace) -> Color {
+    let y = relative_luminance(c, space);
+    Color::from_linear(p::LinSrgb::new(y, y, y))
+}
+
+/// Contrast ratio of `fg` on `bg`, and whether `fg` is the lighter of the
+/// two (light text on a dark background).
+pub fn contrast_directional(fg: Color, bg: Color, space: ColorSpace) -> (f32, bool) {
+    (
+        srgb_appearance(fg, space).get_contrast_ratio(&srgb_appearance(bg, space)),
+        relative_luminance(fg, space) > relative_luminance(bg, space),
+    )
+}
+
+/// The contrast of every color of `rows` against every color of `cols`,
+/// row-major. Each color's luminance is computed once rather than once per
+/// pair.
+pub fn contrast_matrix(
+    rows: &[Color],
+    cols: &[Color],
+    need: ContrastNeed,
+    level: ContrastLevel,
+    space: ColorSpace,
+) -> Vec<ContrastRatio> {
+    let col_luminances: Vec<f32> = cols.iter().map(|c| relative_luminance(*c, space)).collect();
+    let mut out = Vec::with_capacity(rows.len() * cols.len());
+    for row in rows {
+        let y = relative_luminance(*row, space);
+        out.extend(
+            col_luminances
+                .iter()
+                .map(|&col_y| ContrastRatio::from_luminances(y, col_y, need, level)),
+        );
+    }
+    out
+}
+
 pub fn contrast_table(
     rows: Vec<Color>,
     cols: Vec<Color>,
     need: ContrastNeed,
+    level: ContrastLevel,
+    space: ColorSpace,
 ) -> ColorDataTable<ContrastRatio> {
     ColorDataTable::new(rows, cols, "contrast", &|c1, c2| {
-        ContrastRatio::new(c1.get_contrast_ratio(&c2), need)
+        ContrastRatio::for_pair(c1, c2, need, level, space)
     })
 }
+
+/// CIEDE2000 distance between two palette entries. `None` marks a color
+/// compared with itself, which is never worth flagging.
+#[derive(Copy, Clone)]
+pub struct DeltaE {
+    value: Option<f32>,
+}
+
+impl DeltaE {
+    /// Pairs closer than this are hard to tell apart at a glance.
+    pub const PERCEPTIBLE: f32 = 10.;
+}
+
+impl DrawAttention for DeltaE {
+    fn attention(&self) -> Attention {
+        match self.value {
+            Some(v) if v < Self::PERCEPTIBLE => Attention::Bad,
+            _ => Attention::Normal,
+        }
+    }
+}
+
+impl Display for DeltaE {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.value {
+            Some(v) => write!(f, "{v:.1}"),
+            None => write!(f, "-"),
+        }
+    }
+}
+
+/// Pairwise ΔE table of `colors` as seen with vision `v`.
+pub fn delta_e_table(colors: Vec<Color>, v: Vision, space: ColorSpace) -> ColorDataTable<DeltaE> {
+    let mut t = ColorDataTable::new(colors.clone(), colors, "ΔE", &|c1, c2| DeltaE {
+        value: Some(distance_under_vision(c1, c2, v, space)),
+    });
+    for (i, row) in t.data.iter_mut().enumerate() {
+        row[i] = DeltaE { value: None };
+    }
+    t
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn nearby_candidate_skips_the_current_color_within_rounding() {
+        use rand::SeedableRng;
+
+        let space = ColorSpace::Srgb;
+        let candidates = colors_from_hex(&["#ffc247", "#00a1c7"]).unwrap();
+        let mut current = candidates[0];
+        current.red -= 0.1 / 255.;
+        let mut rng = crate::random::Rng::seed_from_u64(0);
+        for _ in 0..10 {
+            let next = random_nearby_candidate(current, &candidates, 1, space, &mut rng);
+            assert_eq!(next, candidates[1]);
+        }
+    }
+
+    #[test]
+    fn fails_column_skips_self_pairs() {
+        let space = ColorSpace::Srgb;
+        let colors = colors_from_hex(&["#1d212f", "#ffc247"]).unwrap();
+        let (need, level) = (ContrastNeed::Text, ContrastLevel::Aa);
+        let square = contrast_table(colors.clone(), colors.clone(), need, level, space);
+        assert!(!square.table().to_string().contains("fails"));
+
+        // A pair that fails on its own still shows up.
+        let close = colors_from_hex(&["#1d212f", "#343a4d"]).unwrap();
+        let table = contrast_table(close.clone(), close, need, level, space).table();
+        assert!(table.to_string().contains("SC 1.4.3"), "{table}");
+    }
+
+    fn any_color() -> impl Strategy<Value = Color> {
+        (0f32..=1., 0f32..=1., 0f32..=1.).prop_map(|(r, g, b)| Color::new(r, g, b))
+    }
+
+    fn any_need() -> impl Strategy<Value = ContrastNeed> {
+        prop_oneof![Just(ContrastNeed::Background), Just(ContrastNeed::Text)]
+    }
+
+    fn any_space() -> impl Strategy<Value = ColorSpace> {
+        prop_oneof![Just(ColorSpace::Srgb), Just(ColorSpace::DisplayP3)]
+    }
+
+    proptest! {
+        #[test]
+        fn contrast_ratio_is_symmetric(r in 1f32..=21., need in any_need()) {
+            let level = ContrastLevel::Aa;
+            let forward = ContrastRatio::new(r, need, level).value();
+            let inverse = ContrastRatio::new(1. / r, need, level).value();
+            prop_assert!((forward - inverse).abs() <= 1e-4 * forward, "{forward} vs {inverse}");
+        }
+
+        #[test]
+        fn contrast_ratio_of_any_pair_is_in_range(
+            c1 in any_color(),
+            c2 in any_color(),
+            need in any_need(),
+            space in any_space(),
+        ) {
+            let ratio = ContrastRatio::for_pair(c1, c2, need, ContrastLevel::Aa, space).value();
+            prop_assert!((1. ..=21.).contains(&ratio), "{ratio}");
+        }
+
+        #[test]
+        fn contrast_cost_accepts_any_pair(
+            c1 in any_color(),
+            c2 in any_color(),
+            need in any_need(),
+            space in any_space(),
+            steepness in 0f32..=10.,
+            center in 0f32..=5.,
+        ) {
+            let ratio = ContrastRatio::for_pair(c1, c2, need, ContrastLevel::Aaa, space);
+            let cost = ratio.cost(ContrastSigmoid { steepness, center }).value();
+            prop_assert!((0. ..=100.).contains(&cost), "{cost}");
+            if ratio.value() < ratio.required_ratio() {
+                prop_assert_eq!(cost, 100.);
+            }
+        }
+    }
+
+    /// Checks that contrast tables flag exactly the ratios below the threshold
+    /// of their need and level: a hair under it is `Bad`, and the threshold
+    /// itself and a hair over are not. Flagged ratios must name the success
+    /// criterion the threshold comes from.
+    #[test]
+    fn contrast_flags_match_the_wcag_thresholds() {
+        let cases = [
+            (ContrastNeed::Background, ContrastLevel::Aa, 3., "1.4.11"),
+            (ContrastNeed::Background, ContrastLevel::Aaa, 3., "1.4.11"),
+            (ContrastNeed::Text, ContrastLevel::Aa, 4.5, "1.4.3"),
+            (ContrastNeed::Text, ContrastLevel::Aaa, 7., "1.4.6"),
+        ];
+        for (need, level, threshold, criterion) in cases {
+            for (value, expected) in [
+                (threshold - 0.01, Attention::Bad),
+                (threshold, Attention::Normal),
+                (threshold + 0.01, Attention::Normal),
+            ] {
+                let ratio = ContrastRatio::new(value, need, level);
+                assert_eq!(
+                    ratio.attention(),
+                    expected,
+                    "{ratio} flagged wrongly at {}",
+                    level.text()
+                );
+                // The cost's hard penalty must agree with the flag.
+                let penalized = ratio.cost(ContrastSigmoid::default()).value() == 100.;
+                assert_eq!(
+                    penalized,
+                    expected == Attention::Bad,
+                    "{ratio} penalty disagrees with its flag at {}",
+                    level.text()
+                );
+                assert_eq!(
+                    ratio.failed_criterion().map(|sc| sc.id),
+                    (expected == Attention::Bad).then_some(criterion),
+                    "{ratio} names the wrong success criterion at {}",
+                    level.text()
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,208 @@
+//! Whole-run configuration files for `--config`.
+
+use serde::Deserialize;
+
+use crate::error::{Error, Result};
+
+/// Every knob of a run, read from a YAML file. Each field is named after
+/// the flag it stands for (with `_` for `-`) and takes the same values, so
+/// the file is turned into flags and parsed before the command line, and
+/// flags given on the command line override it. Repeated flags such as
+/// `protect` add to the file's list instead. Paths are relative to the
+/// working directory, not the config file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RunConfig {
+    /// `optimize`, `compare`, `stability`, `cost`, `grayscale` or
+    /// `minimize`.
+    command: Option<String>,
+    seed: Option<String>,
+    budget: Option<f32>,
+    iterations: Option<u64>,
+    restarts: Option<usize>,
+    threads: Option<usize>,
+    seeds: Option<usize>,
+    wiggle: Option<f32>,
+    #[serde(default)]
+    anneal_wiggle: bool,
+    perturb: Option<String>,
+    phases: Option<String>,
+    phase_rounds: Option<usize>,
+    acceptance: Option<String>,
+    #[serde(default)]
+    shuffle: bool,
+    #[serde(default)]
+    background_only: bool,
+    #[serde(default)]
+    keep_hue_order: bool,
+    #[serde(default)]
+    pin_chroma: bool,
+    hue_band: Option<String>,
+    #[serde(default)]
+    feasible_start: bool,
+    preset: Option<String>,
+    weights: Option<String>,
+    cvd_weights: Option<String>,
+    cvd_severity: Option<f32>,
+    distance_objective: Option<String>,
+    #[serde(default)]
+    no_cvd: bool,
+    #[serde(default)]
+    no_target: bool,
+    vs_preset: Option<String>,
+    vs_weights: Option<String>,
+    contrast_level: Option<String>,
+    contrast_steepness: Option<f32>,
+    contrast_center: Option<f32>,
+    color_space: Option<String>,
+    precision: Option<String>,
+    #[serde(default)]
+    protect: Vec<String>,
+    #[serde(default)]
+    backgrounds: Vec<String>,
+    #[serde(default)]
+    achromatic: Vec<String>,
+    #[serde(default)]
+    importance: Vec<String>,
+    #[serde(default)]
+    text_use: Vec<String>,
+    #[serde(default)]
+    contrast_floor: bool,
+    dark_bg: Option<String>,
+    light_bg: Option<String>,
+    palette: Option<String>,
+    batch: Option<String>,
+    batch_dir: Option<String>,
+    targets: Option<String>,
+    generate: Option<usize>,
+    #[serde(default)]
+    candidates: Vec<String>,
+    #[serde(default)]
+    snap_to: Vec<String>,
+    #[serde(default)]
+    add_color: bool,
+    format: Option<String>,
+    output: Option<String>,
+    #[serde(default)]
+    base16_map: Vec<String>,
+    sort_output: Option<String>,
+    vision_table: Option<String>,
+    luma: Option<String>,
+    #[serde(default)]
+    quiet: bool,
+    #[serde(default)]
+    verbose: bool,
+    #[serde(default)]
+    progress: bool,
+    #[serde(default)]
+    color_preview: bool,
+}
+
+impl RunConfig {
+    pub fn load(path: &str) -> Result<RunConfig> {
+        let config_error = |message: String| Error::Config {
+            path: path.to_string(),
+            message,
+        };
+        let text = std::fs::read_to_string(path).map_err(|e| config_error(e.to_string()))?;
+        serde_yaml::from_str(&text).map_err(|e| config_error(e.to_string()))
+    }
+
+    /// The flags this config stands for, in the order the parser expects.
+    pub fn into_args(self) -> Result<Vec<String>> {
+        let mut args = vec![];
+        let mut value = |flag: &str, value: Option<String>| {
+            if let Some(value) = value {
+                args.push(format!("--{flag}"));
+                args.push(value);
+            }
+        };
+        value("seed", self.seed);
+        value("budget", self.budget.map(|x| x.to_string()));
+        value("iterations", self.iterations.map(|x| x.to_string()));
+        value("restarts", self.restarts.map(|x| x.to_string()));
+        value("threads", self.threads.map(|x| x.to_string()));
+        value("seeds", self.seeds.map(|x| x.to_string()));
+        value("wiggle", self.wiggle.map(|x| x.to_string()));
+        value("perturb", self.perturb);
+        value("phases", self.phases);
+        value("phase-rounds", self.phase_rounds.map(|x| x.to_string()));
+        value("acceptance", self.acceptance);
+        value("hue-band", self.hue_band);
+        value("preset", self.preset);
+        value("weights", self.weights);
+        value("cvd-weights", self.cvd_weights);
+        value("cvd-severity", self.cvd_severity.map(|x| x.to_string()));
+        value("distance-objective", self.distance_objective);
+        value("vs-preset", self.vs_preset);
+        value("vs-weights", self.vs_weights);
+        value("contrast-level", self.contrast_level);
+        value(
+            "contrast-steepness",
+            self.contrast_steepness.map(|x| x.to_string()),
+        );
+        value(
+            "contrast-center",
+            self.contrast_center.map(|x| x.to_string()),
+        );
+        value("color-space", self.color_space);
+        value("precision", self.precision);
+        value("dark-bg", self.dark_bg);
+        value("light-bg", self.light_bg);
+        value("palette", self.palette);
+        value("batch", self.batch);
+        value("batch-dir", self.batch_dir);
+        value("targets", self.targets);
+        value("generate", self.generate.map(|x| x.to_string()));
+        value("format", self.format);
+        value("output", self.output);
+        value("sort-output", self.sort_output);
+        value("vision-table", self.vision_table);
+        value("luma", self.luma);
+        let lists = [
+            ("protect", self.protect),
+            ("backgrounds", self.backgrounds),
+            ("achromatic", self.achromatic),
+            ("importance", self.importance),
+            ("text-use", self.text_use),
+            ("candidates", self.candidates),
+            ("snap-to", self.snap_to),
+            ("base16-map", self.base16_map),
+        ];
+        for (flag, values) in lists {
+            for v in values {
+                value(flag, Some(v));
+            }
+        }
+        let switches = [
+            ("anneal-wiggle", self.anneal_wiggle),
+            ("shuffle", self.shuffle),
+            ("background-only", self.background_only),
+            ("keep-hue-order", self.keep_hue_order),
+            ("pin-chroma", self.pin_chroma),
+            ("feasible-start", self.feasible_start),
+            ("no-cvd", self.no_cvd),
+            ("no-target", self.no_target),
+            ("contrast-floor", self.contrast_floor),
+            ("add-color", self.add_color),
+            ("quiet", self.quiet),
+            ("verbose", self.verbose),
+            ("progress", self.progress),
+            ("color-preview", self.color_preview),
+        ];
+        args.extend(
+            switches
+                .into_iter()
+                .filter(|(_, on)| *on)
+                .map(|(flag, _)| format!("--{flag}")),
+        );
+        match self.command.as_deref() {
+            None | Some("optimize") => {}
+            Some(command @ ("compare" | "stability" | "cost" | "grayscale" | "minimize")) => {
+                args.push(command.to_string())
+            }
+            Some(other) => return Err(Error::unknown("command", other)),
+        }
+        Ok(args)
+    }
+}
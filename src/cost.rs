@@ -1,20 +1,129 @@
-use std::fmt::Display;
+use std::{fmt::Display, str::FromStr};
 
-#[derive(Copy, Clone)]
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::math::cmp_nan_last;
+use crate::sg::BgRole;
+
+#[derive(Copy, Clone, Debug)]
 pub enum ContrastNeed {
     Background,
     Text,
 }
 
-impl ContrastNeed {
-    pub fn minimum_ratio(self) -> f32 {
+/// Which WCAG conformance level contrast is checked against.
+#[derive(Copy, Clone, Debug, Default)]
+pub enum ContrastLevel {
+    /// 4.5:1 for text, 3:1 for non-text.
+    #[default]
+    Aa,
+    /// 7:1 for text.
+    Aaa,
+}
+
+impl ContrastLevel {
+    pub fn text(self) -> &'static str {
         match self {
-            ContrastNeed::Background => 3.,
-            ContrastNeed::Text => 4.5,
+            ContrastLevel::Aa => "aa",
+            ContrastLevel::Aaa => "aaa",
+        }
+    }
+}
+
+impl FromStr for ContrastLevel {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "aa" => Ok(ContrastLevel::Aa),
+            "aaa" => Ok(ContrastLevel::Aaa),
+            _ => Err(Error::unknown("contrast level", s)),
         }
     }
 }
 
+/// The minimum contrast ratio for `need` at `level`. This is the one
+/// threshold behind the cost penalty, the tables' flagging and the
+/// `--backgrounds` pairs, so they can't disagree.
+pub fn required_ratio(need: ContrastNeed, level: ContrastLevel) -> f32 {
+    match (need, level) {
+        // Non-text contrast has no AAA criterion.
+        (ContrastNeed::Background, _) => 3.,
+        (ContrastNeed::Text, ContrastLevel::Aa) => 4.5,
+        (ContrastNeed::Text, ContrastLevel::Aaa) => 7.,
+    }
+}
+
+/// A WCAG 2.x success criterion, for naming what a failing pair violates
+/// in audits.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SuccessCriterion {
+    /// The SC number, e.g. `1.4.3`.
+    pub id: &'static str,
+    pub name: &'static str,
+}
+
+impl Display for SuccessCriterion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SC {} {}", self.id, self.name)
+    }
+}
+
+/// The success criterion that [`required_ratio`] comes from: 1.4.3 for
+/// text at AA, 1.4.6 for text at AAA, and 1.4.11 for non-text contrast at
+/// either level.
+pub fn success_criterion(need: ContrastNeed, level: ContrastLevel) -> SuccessCriterion {
+    let (id, name) = match (need, level) {
+        (ContrastNeed::Background, _) => ("1.4.11", "Non-text Contrast"),
+        (ContrastNeed::Text, ContrastLevel::Aa) => ("1.4.3", "Contrast (Minimum)"),
+        (ContrastNeed::Text, ContrastLevel::Aaa) => ("1.4.6", "Contrast (Enhanced)"),
+    };
+    SuccessCriterion { id, name }
+}
+
+/// Shape of the WCAG contrast cost for pairs that meet the minimum ratio:
+/// `100 / (1 + exp(steepness * (ratio - minimum - center)))`, so 50 at
+/// `center` above the minimum and falling towards 0 beyond it. Pairs below
+/// the minimum always cost 100.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ContrastSigmoid {
+    /// How fast the cost falls per unit of ratio. Steep curves are nearly
+    /// free once a pair clears `center`, so the optimizer settles for
+    /// contrast just past it and spends its effort elsewhere; shallow ones
+    /// keep rewarding extra contrast, pushing towards maximally contrasting
+    /// pairs at the expense of distance and target terms.
+    pub steepness: f32,
+    /// How far above the minimum ratio the cost reaches 50, i.e. the margin
+    /// the optimizer aims for.
+    pub center: f32,
+}
+
+impl Default for ContrastSigmoid {
+    fn default() -> Self {
+        ContrastSigmoid {
+            steepness: 4.,
+            center: 0.,
+        }
+    }
+}
+
+/// A foreground/background pair that must always meet `min_ratio`,
+/// regardless of how the other pairs fare.
+#[derive(Copy, Clone, Debug)]
+pub struct ProtectedPair {
+    pub fg_index: usize,
+    pub bg_role: BgRole,
+    pub min_ratio: f32,
+}
+
+impl ProtectedPair {
+    /// Added to the contrast cost for every violated pair. This is
+    /// deliberately outside the 0..=100 range of the RMS aggregate so a
+    /// single violation can't be averaged away.
+    pub const PENALTY: f32 = 100.;
+}
+
 #[derive(Copy, Clone)]
 // Utility struct for inserting assertions about cost values.
 //
@@ -36,12 +145,21 @@ impl ScaledCost {
         assert!(value <= 100.0);
         ScaledCost { value }
     }
+    /// Combines sub-scores that are each in 0..=100 using weights that sum
+    /// to 1 (see [`Weights::initialize`]). Mathematically the result is in
+    /// range too, but the weights only sum to 1 up to rounding, so a sum of
+    /// maximal scores can land a hair above 100; that excess is clamped
+    /// rather than tripping the assertion in [`ScaledCost::new`].
+    pub fn weighted_sum(terms: &[(ScaledCost, f32)]) -> ScaledCost {
+        let sum: f32 = terms.iter().map(|(cost, w)| cost.value * w).sum();
+        ScaledCost::new(sum.min(100.))
+    }
     pub fn value(&self) -> f32 {
         self.value
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct TotalCost {
     pub contrast_cost: f32,
     pub distance_cost: f32,
@@ -50,25 +168,28 @@ pub struct TotalCost {
     pub protanopia_cost: f32,
     pub deuteranopia_cost: f32,
     pub tritanopia_cost: f32,
+    pub bg_clearance_cost: f32,
 }
 
 impl Display for TotalCost {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "contrast={:.2}  distance={:.2}  target={:.2}  range={:.2}  a11y={:.2},{:.2},{:.2}",
+            "contrast={:.2}  distance={:.2}  target={:.2}  range={:.2}  a11y={:.2},{:.2},{:.2}  clearance={:.2}",
             self.contrast_cost,
             self.distance_cost,
             self.target_cost,
             self.range_cost,
             self.protanopia_cost,
             self.deuteranopia_cost,
-            self.tritanopia_cost
+            self.tritanopia_cost,
+            self.bg_clearance_cost
         )
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Weights {
     pub contrast_weight: f32,
     pub distance_weight: f32,
@@ -77,6 +198,7 @@ pub struct Weights {
     pub protanopia_weight: f32,
     pub deuteranopia_weight: f32,
     pub tritanopia_weight: f32,
+    pub bg_clearance_weight: f32,
 
     pub distance_bg_bg_weight: f32,
     pub distance_bg_fg_weight: f32,
@@ -90,6 +212,67 @@ pub struct Weights {
 }
 
 impl Weights {
+    /// Checks that each group of weights that [`Weights::initialize`]
+    /// normalizes sums to about 1.
+    pub fn check_sums(&self) -> Result<()> {
+        let groups = [
+            (
+                "distance",
+                self.distance_bg_bg_weight
+                    + self.distance_bg_fg_weight
+                    + self.distance_fg_fg_weight,
+            ),
+            ("target", self.target_bg_weight + self.target_fg_weight),
+            (
+                "contrast",
+                self.contrast_bg_bg_weight + self.contrast_bg_fg_weight,
+            ),
+        ];
+        for (name, sum) in groups {
+            if !(0.99..=1.01).contains(&sum) {
+                return Err(Error::Usage(format!(
+                    "The {name} weights must sum to 1, got {sum}"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Splits the current total of the three CVD weights as `cvd` says,
+    /// so the CVD terms as a whole weigh the same as before.
+    pub fn with_cvd_weights(mut self, cvd: CvdWeights) -> Self {
+        let Some(shares) = cvd.shares() else {
+            return self;
+        };
+        let total = self.protanopia_weight + self.deuteranopia_weight + self.tritanopia_weight;
+        let share_sum: f32 = shares.iter().sum();
+        [
+            self.protanopia_weight,
+            self.deuteranopia_weight,
+            self.tritanopia_weight,
+        ] = shares.map(|s| total * s / share_sum);
+        self
+    }
+
+    /// Drops the CVD terms, which also lets the cost skip simulating them.
+    pub fn without_cvd(self) -> Self {
+        Weights {
+            protanopia_weight: 0.,
+            deuteranopia_weight: 0.,
+            tritanopia_weight: 0.,
+            ..self
+        }
+    }
+
+    /// Drops the target term, which also lets the cost skip measuring the
+    /// distance to the targets.
+    pub fn without_target(self) -> Self {
+        Weights {
+            target_weight: 0.,
+            ..self
+        }
+    }
+
     pub fn initialize(mut self) -> Self {
         assert!((0.99..=1.01).contains(
             &(self.distance_bg_bg_weight + self.distance_bg_fg_weight + self.distance_fg_fg_weight)
@@ -104,6 +287,113 @@ impl Weights {
     }
 }
 
+/// How the total CVD weight is split between the protanopia, deuteranopia
+/// and tritanopia terms.
+#[derive(Copy, Clone, Debug, Default)]
+pub enum CvdWeights {
+    /// As the preset and weights file set them.
+    #[default]
+    Custom,
+    /// A third each.
+    Equal,
+    /// In proportion to how common each deficiency is among men: about 1%
+    /// protan, 6% deutan and 0.01% tritan.
+    Prevalence,
+}
+
+impl CvdWeights {
+    /// Protan, deutan and tritan shares, or `None` to keep the weights.
+    fn shares(self) -> Option<[f32; 3]> {
+        match self {
+            CvdWeights::Custom => None,
+            CvdWeights::Equal => Some([1., 1., 1.]),
+            CvdWeights::Prevalence => Some([1., 6., 0.01]),
+        }
+    }
+}
+
+impl FromStr for CvdWeights {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "custom" => Ok(CvdWeights::Custom),
+            "equal" => Ok(CvdWeights::Equal),
+            "prevalence" => Ok(CvdWeights::Prevalence),
+            _ => Err(Error::unknown("CVD weighting", s)),
+        }
+    }
+}
+
+/// Named starting points for [`Weights`].
+#[derive(Copy, Clone)]
+pub enum Preset {
+    /// The long-standing default trade-off.
+    Balanced,
+    /// Favors contrast, CVD distinguishability and background clearance
+    /// over staying close to the brand colors.
+    AccessibilityFirst,
+    /// Keeps the colors close to their targets, at some cost to contrast
+    /// and distinguishability.
+    BrandFaithful,
+}
+
+impl Preset {
+    pub fn weights(self) -> Weights {
+        let balanced = Weights {
+            contrast_weight: 2.,
+            distance_weight: 0.75,
+            range_weight: 0.25,
+            target_weight: 0.50,
+            protanopia_weight: 0.33,
+            deuteranopia_weight: 0.33,
+            tritanopia_weight: 0.33,
+            bg_clearance_weight: 1.,
+            distance_bg_bg_weight: 0.1,
+            distance_bg_fg_weight: 0.2,
+            distance_fg_fg_weight: 0.7,
+            target_bg_weight: 0.1,
+            target_fg_weight: 0.9,
+            contrast_bg_bg_weight: 0.2,
+            contrast_bg_fg_weight: 0.8,
+        };
+        match self {
+            Preset::Balanced => balanced,
+            Preset::AccessibilityFirst => Weights {
+                contrast_weight: 3.,
+                target_weight: 0.25,
+                protanopia_weight: 0.75,
+                deuteranopia_weight: 0.75,
+                tritanopia_weight: 0.75,
+                bg_clearance_weight: 1.5,
+                ..balanced
+            },
+            Preset::BrandFaithful => Weights {
+                distance_weight: 0.5,
+                target_weight: 1.5,
+                protanopia_weight: 0.2,
+                deuteranopia_weight: 0.2,
+                tritanopia_weight: 0.2,
+                ..balanced
+            },
+        }
+        .initialize()
+    }
+}
+
+impl FromStr for Preset {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "balanced" => Ok(Preset::Balanced),
+            "accessibility-first" => Ok(Preset::AccessibilityFirst),
+            "brand-faithful" => Ok(Preset::BrandFaithful),
+            _ => Err(Error::unknown("preset", s)),
+        }
+    }
+}
+
 impl TotalCost {
     pub fn total(&self, w: &Weights) -> f32 {
         w.contrast_weight * self.contrast_cost
@@ -113,5 +403,62 @@ impl TotalCost {
             + w.protanopia_weight * self.protanopia_cost
             + w.deuteranopia_weight * self.deuteranopia_cost
             + w.tritanopia_weight * self.tritanopia_cost
+            + w.bg_clearance_weight * self.bg_clearance_cost
     }
+
+    /// Each term's name and unweighted value. Unlike the weighted terms,
+    /// these can be compared between runs with different weights.
+    pub fn terms(&self) -> [(&'static str, f32); 8] {
+        [
+            ("contrast", self.contrast_cost),
+            ("distance", self.distance_cost),
+            ("range", self.range_cost),
+            ("target", self.target_cost),
+            ("protanopia", self.protanopia_cost),
+            ("deuteranopia", self.deuteranopia_cost),
+            ("tritanopia", self.tritanopia_cost),
+            ("bg clearance", self.bg_clearance_cost),
+        ]
+    }
+
+    /// Each term's name and its contribution to [`TotalCost::total`].
+    pub fn weighted_terms(&self, w: &Weights) -> [(&'static str, f32); 8] {
+        [
+            ("contrast", w.contrast_weight * self.contrast_cost),
+            ("distance", w.distance_weight * self.distance_cost),
+            ("range", w.range_weight * self.range_cost),
+            ("target", w.target_weight * self.target_cost),
+            ("protanopia", w.protanopia_weight * self.protanopia_cost),
+            (
+                "deuteranopia",
+                w.deuteranopia_weight * self.deuteranopia_cost,
+            ),
+            ("tritanopia", w.tritanopia_weight * self.tritanopia_cost),
+            (
+                "bg clearance",
+                w.bg_clearance_weight * self.bg_clearance_cost,
+            ),
+        ]
+    }
+
+    /// The term contributing the most to the total, which is usually the
+    /// first weight worth adjusting when a result looks off.
+    pub fn dominant_term(&self, w: &Weights) -> (&'static str, f32) {
+        self.weighted_terms(w)
+            .into_iter()
+            .max_by(|(_, a), (_, b)| cmp_nan_last(*a, *b))
+            .expect("TotalCost has terms")
+    }
+}
+
+/// Foreground colors closer than this (CIEDE2000) to a background start
+/// blending into it.
+pub const MIN_BG_DISTANCE: f32 = 15.;
+
+/// Penalizes the closest fg ↔ bg pair, from 0 at [`MIN_BG_DISTANCE`] up to
+/// 100 when the colors coincide. Unlike the RMS distance term, a single
+/// color sitting on top of a background can't be averaged away.
+pub fn bg_clearance_cost(min_distance: f32) -> ScaledCost {
+    let shortfall = (MIN_BG_DISTANCE - min_distance).max(0.) / MIN_BG_DISTANCE;
+    ScaledCost::new(100. * shortfall.min(1.))
 }
@@ -0,0 +1,54 @@
+use std::fmt::Display;
+
+/// Errors caused by user input: command-line arguments, config files and
+/// palettes. Broken internal invariants still panic.
+#[derive(Debug)]
+pub enum Error {
+    /// A hex color string that couldn't be parsed.
+    ParseColor { input: String },
+    /// A name that doesn't match any known value of `kind`.
+    UnknownName { kind: &'static str, input: String },
+    /// A malformed or out-of-range command-line argument.
+    Usage(String),
+    /// A config file that couldn't be read, or holds invalid values.
+    Config { path: String, message: String },
+    /// The report couldn't be written out.
+    Output { path: String, message: String },
+    /// An operation was given fewer colors than it needs.
+    TooFewColors { needed: usize, got: usize },
+    /// A palette checked by the `cost` command broke this many hard
+    /// constraints.
+    ConstraintsViolated(usize),
+    /// This many palette files of a `--batch` run couldn't be optimized.
+    BatchFailed(usize),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn unknown(kind: &'static str, input: &str) -> Error {
+        Error::UnknownName {
+            kind,
+            input: input.to_string(),
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::ParseColor { input } => write!(f, "Invalid color {input:?}, expected #rrggbb"),
+            Error::UnknownName { kind, input } => write!(f, "Unknown {kind} {input}"),
+            Error::Usage(message) => write!(f, "{message}"),
+            Error::Config { path, message } => write!(f, "{path}: {message}"),
+            Error::Output { path, message } => write!(f, "Can't write {path}: {message}"),
+            Error::TooFewColors { needed, got } => {
+                write!(f, "Expected at least {needed} colors, got {got}")
+            }
+            Error::ConstraintsViolated(n) => write!(f, "{n} hard constraint(s) violated"),
+            Error::BatchFailed(n) => write!(f, "{n} palette file(s) failed"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -1,19 +1,38 @@
-use std::{env::args, fmt::Display};
+use std::{
+    fmt::Display,
+    io::{IsTerminal, Write},
+    time::Duration,
+};
 
-use palette::RelativeContrast;
-use rand::{Rng as RandRng, SeedableRng};
+use palette::Lch;
+use rand::{seq::SliceRandom, Rng as RandRng, SeedableRng};
+use rayon::prelude::*;
+use serde::Deserialize;
 
+mod apca;
+mod ase;
+mod base16;
 mod brettel;
+mod cli;
 mod color;
+mod config;
 mod convert;
 mod cost;
+mod error;
 mod math;
+mod okhsl;
+mod preview;
 mod random;
 mod sg;
 
+use crate::apca::TextUse;
 use crate::brettel::*;
+use crate::cli::{
+    AcceptanceRule, Command, DistanceObjective, Format, Options, Phases, SortOutput, Verbosity,
+};
 use crate::color::*;
 use crate::cost::*;
+use crate::error::{Error, Result};
 use crate::math::*;
 use crate::random::*;
 use crate::sg::*;
@@ -26,19 +45,410 @@ struct State {
     fg_colors: Vec<Color>,
     target_bg_colors: Vec<Color>,
     target_fg_colors: Vec<Color>,
+    protected_pairs: Vec<ProtectedPair>,
+    // The WCAG level the contrast cost penalizes against.
+    contrast_level: ContrastLevel,
+    // How the WCAG contrast cost rewards pairs above that level's minimum.
+    contrast_sigmoid: ContrastSigmoid,
+    // How much each fg color counts, indexed like fg_colors. A color's
+    // weight scales its pairs in the distance terms (fg ↔ fg pairs by the
+    // product of both weights) and its entry in the target term, on top of
+    // distance_fg_fg_weight etc. All 1.0 by default.
+    fg_importance: Vec<f32>,
+    // The intended typography of each fg color, indexed like fg_colors.
+    // Colors with a use must reach its APCA Lc on every active background
+    // rather than the WCAG ratio for contrast_level.
+    fg_text_use: Vec<Option<TextUse>>,
+    // The LCH chroma each fg color is pinned to, indexed like fg_colors.
+    // Pinned colors only move in hue and lightness, and moves that clip
+    // them more than CHROMA_TOLERANCE away are rejected.
+    fg_pinned_chroma: Vec<Option<f32>>,
+    // Severity in [0, 1] at which the CVD cost terms simulate their
+    // deficiency; 1 (the default) is full dichromacy.
+    cvd_severity: f32,
+    // Grayscale coefficients for the achromatic visions.
+    luma: Luma,
+    // The RGB space the colors' channels are in, which every perceptual
+    // conversion needs. Set it with set_color_space so the caches follow.
+    space: ColorSpace,
+    // The float type the cost sums in.
+    precision: Precision,
+    // Whether the distance terms (for every vision) score the RMS or the
+    // worst of the pairwise shortfalls.
+    distance_objective: DistanceObjective,
+    // Indexed like color_slot; locked slots are never perturbed but still
+    // count towards every cost term.
+    locked: Vec<bool>,
+    // If set, fg indices in ascending LCH hue order as they were given.
+    // Moves that would change this order are rejected outright.
+    fg_hue_order: Option<Vec<usize>>,
+    // If set, moves that take a chromatic fg color out of this band of
+    // hues are rejected outright.
+    fg_hue_band: Option<HueBand>,
+    // Indexed like bg_color_array; these backgrounds are held at or below
+    // MAX_NEUTRAL_CHROMA, both at the start and after every move.
+    bg_achromatic: Vec<bool>,
+    // LCH versions of bg_colors.into_array() and fg_colors, kept in sync
+    // by sync_slot so the distance term doesn't reconvert unchanged colors.
+    bg_lch: Vec<Lch>,
+    fg_lch: Vec<Lch>,
+    // Distance from each of bg_color_array and fg_colors to its closest
+    // target, also kept in sync by sync_slot.
+    bg_target_distances: Vec<f32>,
+    fg_target_distances: Vec<f32>,
+    // Contrast cost of each fg color, indexed like fg_colors, against each
+    // background it's checked on: bg_color_array for the WCAG ratio, or
+    // every active background for colors with a text use. Kept in sync by
+    // sync_slot, and rebuilt when the level or text uses change.
+    fg_contrast_costs: Vec<Vec<f32>>,
     weights: Weights,
 }
 
+/// A foreground's contrast as text on one background; see
+/// [`State::text_contrast`].
+enum TextContrast {
+    /// The WCAG ratio, for colors without a text use.
+    Wcag(ContrastRatio),
+    /// The magnitude of the APCA Lc, and the use it's judged for.
+    Apca { lc: f32, text_use: TextUse },
+}
+
+impl TextContrast {
+    fn passes(&self) -> bool {
+        match self {
+            TextContrast::Wcag(ratio) => ratio.value() >= ratio.required_ratio(),
+            TextContrast::Apca { lc, text_use } => *lc >= text_use.required_lc(),
+        }
+    }
+}
+
+impl Display for TextContrast {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TextContrast::Wcag(ratio) => write!(
+                f,
+                "{ratio} (minimum {:.2}:1, {})",
+                ratio.required_ratio(),
+                ratio.success_criterion()
+            ),
+            TextContrast::Apca { lc, text_use } => write!(
+                f,
+                "Lc {lc:.1} (minimum {} for {text_use} text)",
+                text_use.required_lc()
+            ),
+        }
+    }
+}
+
 #[derive(Default)]
 struct ScratchBuffers {
     // For color transformation (before distance computation)
-    bg_colors: Vec<Color>,
-    fg_colors: Vec<Color>,
+    bg_lch: Vec<Lch>,
+    fg_lch: Vec<Lch>,
 
     // Intermediate distances/contrast/target deltas.
     bg_to_bg: Vec<f32>,
     bg_to_fg: Vec<f32>,
     fg_to_fg: Vec<f32>,
+
+    // Order in which color slots are perturbed during a sweep.
+    order: Vec<usize>,
+}
+
+/// Knobs for a single optimizer run that don't affect the cost function.
+#[derive(Clone)]
+struct OptimizeConfig {
+    /// Print the cost every this many temperature steps.
+    log_every: Option<u64>,
+    /// Perturb the color slots in a random order at each temperature step
+    /// instead of in index order. This draws extra values from the RNG, so
+    /// a given seed produces different palettes with and without it.
+    shuffle_order: bool,
+    /// Largest change to a color channel (in 0..=1) per proposed move.
+    wiggle: f32,
+    /// Shrink the wiggle as the temperature drops, down to a tenth of
+    /// `wiggle` at the cutoff, so late steps refine rather than explore.
+    anneal_wiggle: bool,
+    /// Color space the moves are made in.
+    perturbation: Perturbation,
+    /// Stop after this many temperature steps even if the schedule (or the
+    /// budget) hasn't run out.
+    max_iterations: Option<u64>,
+    /// Whether foreground and background colors are annealed together.
+    phases: Phases,
+    /// With [`Phases::Alternating`], how many background-then-foreground
+    /// rounds to run. Each phase runs a full schedule (or an equal share of
+    /// the budget).
+    phase_rounds: usize,
+    /// Which uphill moves are taken.
+    acceptance_rule: AcceptanceRule,
+    /// If non-empty, foreground moves jump to one of the nearest of these
+    /// colors instead of perturbing continuously, so the optimizer only
+    /// assigns colors from this set. Backgrounds still move freely.
+    candidates: Vec<Color>,
+}
+
+impl Default for OptimizeConfig {
+    fn default() -> Self {
+        OptimizeConfig {
+            log_every: None,
+            shuffle_order: false,
+            wiggle: 0.05,
+            anneal_wiggle: false,
+            perturbation: Perturbation::Srgb,
+            max_iterations: None,
+            phases: Phases::Joint,
+            phase_rounds: 1,
+            acceptance_rule: AcceptanceRule::Metropolis,
+            candidates: vec![],
+        }
+    }
+}
+
+impl OptimizeConfig {
+    fn hit_iteration_cap(&self, n_iterations: u64) -> bool {
+        self.max_iterations.is_some_and(|max| n_iterations >= max)
+    }
+
+    fn wiggle_at(&self, temperature: f32) -> f32 {
+        if !self.anneal_wiggle {
+            return self.wiggle;
+        }
+        // Fraction of the schedule (in log space) that is still ahead.
+        let remaining = (temperature / State::CUTOFF).log10()
+            / (State::INITIAL_TEMPERATURE / State::CUTOFF).log10();
+        self.wiggle * remaining.clamp(0.1, 1.)
+    }
+}
+
+/// Counts of proposed vs. accepted moves, overall and per decade of
+/// temperature (decade 0 starts at the initial temperature), plus how much
+/// the proposals had to be clamped back into the sRGB gamut.
+#[derive(Clone, Default)]
+struct AcceptanceStats {
+    accepted: u64,
+    proposed: u64,
+    by_decade: Vec<(u64, u64)>,
+    /// Number of proposals that fell outside the gamut.
+    clipped: u64,
+    /// Sum of [`clip_delta`] over all proposals.
+    total_clipping: f32,
+}
+
+impl AcceptanceStats {
+    fn record(&mut self, decade: usize, accepted: bool) {
+        if self.by_decade.len() <= decade {
+            self.by_decade.resize(decade + 1, (0, 0));
+        }
+        let entry = &mut self.by_decade[decade];
+        entry.0 += accepted as u64;
+        entry.1 += 1;
+        self.accepted += accepted as u64;
+        self.proposed += 1;
+    }
+
+    fn merge(&mut self, other: &AcceptanceStats) {
+        if self.by_decade.len() < other.by_decade.len() {
+            self.by_decade.resize(other.by_decade.len(), (0, 0));
+        }
+        for (entry, (accepted, proposed)) in self.by_decade.iter_mut().zip(&other.by_decade) {
+            entry.0 += accepted;
+            entry.1 += proposed;
+        }
+        self.accepted += other.accepted;
+        self.proposed += other.proposed;
+        self.clipped += other.clipped;
+        self.total_clipping += other.total_clipping;
+    }
+
+    fn record_clipping(&mut self, delta: f32) {
+        // Ignore round-off from color space conversions.
+        if delta > 1e-5 {
+            self.clipped += 1;
+            self.total_clipping += delta;
+        }
+    }
+
+    fn rate(accepted: u64, proposed: u64) -> f32 {
+        if proposed == 0 {
+            return 0.;
+        }
+        100. * (accepted as f32) / (proposed as f32)
+    }
+}
+
+/// What ended an optimizer run.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum StopReason {
+    /// The temperature dropped below `State::CUTOFF`.
+    Cutoff,
+    /// The time budget ran out.
+    Budget,
+    /// `OptimizeConfig::max_iterations` was reached.
+    IterationCap,
+}
+
+/// A one-letter accessibility verdict for a palette; see [`grade`].
+#[derive(Copy, Clone, Debug)]
+enum Grade {
+    A,
+    B,
+    C,
+    F,
+}
+
+impl Display for Grade {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+/// The measurements behind a [`Grade`].
+struct GradeInputs {
+    /// Share of fg ↔ active bg pairs meeting the text contrast minimum.
+    contrast_pass_fraction: f32,
+    /// Smallest fg ↔ fg CIEDE2000 distance under any simulated CVD.
+    min_cvd_distance: f32,
+    /// Smallest L* gap between two fg colors printed in grayscale.
+    min_gray_distance: f32,
+}
+
+/// Grades `state`'s palette on contrast, CVD distinguishability and
+/// grayscale separability:
+/// - A: at least 95% of pairs pass, and every fg pair stays perceptibly
+///   apart both under CVD and in grayscale.
+/// - B: at least 80% pass, and pairs stay at least half that far apart.
+/// - C: at least half of the pairs pass.
+/// - F: otherwise.
+fn grade(state: &State) -> (Grade, GradeInputs) {
+    let level = state.contrast_level;
+    let min_ratio = required_ratio(ContrastNeed::Text, level);
+    let ratios = contrast_matrix(
+        &state.fg_colors,
+        &state.bg_colors.into_array(),
+        ContrastNeed::Text,
+        level,
+        state.space,
+    );
+    let n_passing = ratios.iter().filter(|r| r.value() >= min_ratio).count();
+    let contrast_pass_fraction = n_passing as f32 / ratios.len().max(1) as f32;
+
+    let mut distances = vec![];
+    let mut min_cvd_distance = f32::INFINITY;
+    for v in Vision::all().skip(1) {
+        let seen: Vec<Lch> = state
+            .fg_colors
+            .iter()
+            .map(|c| to_lch(simulate_vision(*c, v, state.luma, state.space), state.space))
+            .collect();
+        pairwise_distances(&seen, &mut distances);
+        if let Some(stats) = distance_stats(&distances) {
+            min_cvd_distance = min_cvd_distance.min(stats.min);
+        }
+    }
+
+    let mut gray: Vec<f32> = state
+        .fg_colors
+        .iter()
+        .map(|c| to_lch(grayscale(*c, state.space), state.space).l)
+        .collect();
+    gray.sort_by(|a, b| cmp_nan_last(*a, *b));
+    let min_gray_distance = gray
+        .windows(2)
+        .map(|w| w[1] - w[0])
+        .fold(f32::INFINITY, f32::min);
+
+    let apart = |scale: f32| {
+        min_cvd_distance >= DeltaE::PERCEPTIBLE * scale
+            && min_gray_distance >= MIN_GRAY_DISTANCE * scale
+    };
+    let grade = if contrast_pass_fraction >= 0.95 && apart(1.) {
+        Grade::A
+    } else if contrast_pass_fraction >= 0.8 && apart(0.5) {
+        Grade::B
+    } else if contrast_pass_fraction >= 0.5 {
+        Grade::C
+    } else {
+        Grade::F
+    };
+    let inputs = GradeInputs {
+        contrast_pass_fraction,
+        min_cvd_distance,
+        min_gray_distance,
+    };
+    (grade, inputs)
+}
+
+/// How far the contrast floor moved one foreground color.
+struct Nudge {
+    fg_index: usize,
+    /// Change in L*, positive for lighter.
+    delta_l: f32,
+    /// CIEDE2000 distance between the color before and after.
+    delta_e: f32,
+    /// False if no lightness got the color above the floor on every
+    /// background it was checked against; the color is then left as it was.
+    passes: bool,
+}
+
+impl Nudge {
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "index": self.fg_index,
+            "delta_l": self.delta_l,
+            "delta_e": self.delta_e,
+            "passes": self.passes,
+        })
+    }
+
+    /// Writes one line per nudge under `title`; `against` names the
+    /// backgrounds a color that couldn't pass was checked against.
+    fn write_all(
+        f: &mut std::fmt::Formatter<'_>,
+        title: &str,
+        nudges: &[Nudge],
+        against: &str,
+    ) -> std::fmt::Result {
+        writeln!(f, "{title}: {} color(s) nudged", nudges.len())?;
+        for nudge in nudges {
+            if nudge.passes {
+                writeln!(
+                    f,
+                    "  color {}: L* {:+.1} (ΔE {:.1})",
+                    nudge.fg_index, nudge.delta_l, nudge.delta_e
+                )?;
+            } else {
+                writeln!(
+                    f,
+                    "  color {}: no lightness passes on {against}; left as is",
+                    nudge.fg_index
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 64-bit FNV-1a of `text`, as 16 hex digits. Unlike `std`'s hashers it's
+/// specified bit for bit, so it stays the same across machines and Rust
+/// versions, which is what a cache key needs.
+fn stable_hash(text: &str) -> String {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+    let hash = text.bytes().fold(OFFSET_BASIS, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(PRIME)
+    });
+    format!("{hash:016x}")
+}
+
+/// [`stable_hash`] of a palette's hex codes, so it only changes when some
+/// color moves by at least one 8-bit step.
+fn palette_hash(bg: &[Color], fg: &[Color]) -> String {
+    stable_hash(&format!(
+        "{};{}",
+        hex_colors(bg).join(","),
+        hex_colors(fg).join(",")
+    ))
 }
 
 struct Report {
@@ -46,31 +456,581 @@ struct Report {
     final_cost: TotalCost,
     start_state: State,
     final_state: State,
-    duration: std::time::Duration,
+    duration: Duration,
+    /// Set when the run was bounded by time rather than by the schedule.
+    budget: Option<Duration>,
     n_iterations: u64,
+    stop_reason: StopReason,
+    acceptance: AcceptanceStats,
+    /// Which of `n_restarts` independent runs produced this report.
+    restart: usize,
+    n_restarts: usize,
+    /// If the final foregrounds were reordered, the new position of each
+    /// input color (indexed by input position).
+    fg_positions: Option<Vec<usize>>,
+    /// The foregrounds that the contrast floor moved, if it was applied.
+    nudges: Option<Vec<Nudge>>,
+    /// The foregrounds moved to a feasible start before annealing, if
+    /// that was asked for; `start_state` is already nudged.
+    start_nudges: Option<Vec<Nudge>>,
+    /// The seed of the base RNG, when known; `--seed` reproduces the run.
+    seed: Option<Seed>,
+    config: OptimizeConfig,
     weights: Weights,
 }
 
+impl Report {
+    /// Combines the report of a run with that of a run continuing from its
+    /// final state.
+    fn then(mut self, next: Report) -> Report {
+        self.final_cost = next.final_cost;
+        self.final_state = next.final_state;
+        self.duration += next.duration;
+        self.budget = match (self.budget, next.budget) {
+            (Some(a), Some(b)) => Some(a + b),
+            _ => None,
+        };
+        self.n_iterations += next.n_iterations;
+        self.stop_reason = next.stop_reason;
+        self.acceptance.merge(&next.acceptance);
+        self
+    }
+
+    /// Percent reduction in total cost from start to final; negative if
+    /// the run made things worse.
+    fn improvement_pct(&self) -> f32 {
+        let start = self.start_cost.total(&self.weights);
+        if start == 0. {
+            return 0.;
+        }
+        100. * (start - self.final_cost.total(&self.weights)) / start
+    }
+
+    /// One line with the costs, time and worst contrast, e.g.
+    /// `light: 42.1→18.7 (-55%) in 1.3s, worst contrast 3.20:1`, for
+    /// grepping through sweeps.
+    fn summary_line(&self, mode: Mode) -> String {
+        let mut line = format!(
+            "{}: {:.1}→{:.1} ({:+.0}%) in {:.1}s",
+            mode.text(),
+            self.start_cost.total(&self.weights),
+            self.final_cost.total(&self.weights),
+            -self.improvement_pct(),
+            self.duration.as_secs_f32()
+        );
+        if let Some(ratio) = self.final_state.worst_contrast() {
+            line += &format!(", worst contrast {ratio}");
+        }
+        line
+    }
+
+    /// Identifies the final palette (active backgrounds and foregrounds),
+    /// for skipping downstream steps when a rerun didn't change it.
+    fn palette_hash(&self) -> String {
+        let state = &self.final_state;
+        palette_hash(&state.bg_colors.into_array(), &state.fg_colors)
+    }
+
+    /// Identifies the run's inputs: the starting palette and everything in
+    /// [`Report::parameters_json`] except which restart won. Two runs with
+    /// the same config hash produce the same palette unless they were
+    /// bounded by `--budget`.
+    fn config_hash(&self) -> String {
+        let state = &self.start_state;
+        let mut parameters = self.parameters_json();
+        parameters["best_restart"] = serde_json::Value::Null;
+        let inputs = serde_json::json!({
+            "background": hex_colors(&state.bg_colors.into_array()),
+            "foreground": hex_colors(&state.fg_colors),
+            "parameters": parameters,
+        });
+        stable_hash(&inputs.to_string())
+    }
+
+    /// Above this share of clipped moves, the perturbation is mostly
+    /// pushing colors against the gamut boundary rather than exploring.
+    const CLIPPING_WARN_PCT: f32 = 20.;
+
+    /// Foregrounds with less chroma headroom than this are flagged as
+    /// fragile.
+    const FRAGILE_HEADROOM: f32 = 5.;
+
+    /// Moves each foreground color that falls short of the text contrast
+    /// minimum on an active background to the nearest lightness (in LCH)
+    /// that meets it everywhere, keeping its hue and chroma. Deterministic,
+    /// so the shipped palette passes even when annealing left a few pairs
+    /// just below the line; the price is some drift from the targets.
+    fn apply_contrast_floor(&mut self) {
+        let state = &mut self.final_state;
+        let active = state.bg_colors.into_array();
+        let nudges = (0..state.fg_colors.len())
+            .filter_map(|i| state.nudge_to_contrast(i, &active))
+            .collect();
+        self.final_cost = state.total_cost(&mut ScratchBuffers::default());
+        self.nudges = Some(nudges);
+    }
+
+    /// Stable-sorts the final foreground colors by LCH hue, recording where
+    /// each input color ended up.
+    fn sort_final_by_hue(&mut self) {
+        let state = &self.final_state;
+        let mut order: Vec<usize> = (0..state.fg_colors.len()).collect();
+        order.sort_by(|&i, &j| {
+            let hue = |k: usize| state.fg_lch[k].hue.to_positive_degrees();
+            cmp_nan_last(hue(i), hue(j))
+        });
+        self.fg_positions = Some(self.final_state.permute_fg(&order));
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let palette = |state: &State| {
+            serde_json::json!({
+                "background": hex_colors(&state.bg_colors.into_array()),
+                "foreground": hex_colors(&state.fg_colors),
+            })
+        };
+        let mut doc = serde_json::json!({
+            "start": palette(&self.start_state),
+            "final": palette(&self.final_state),
+            "cost": {
+                "start": self.start_cost,
+                "final": self.final_cost,
+                "improvement_pct": self.improvement_pct(),
+            },
+        });
+        if let Some(positions) = &self.fg_positions {
+            doc["final"]["foreground_positions"] = serde_json::json!(positions);
+        }
+        if let Some(nudges) = &self.nudges {
+            doc["contrast_floor"] = nudges.iter().map(Nudge::to_json).collect();
+        }
+        if let Some(nudges) = &self.start_nudges {
+            doc["feasible_start"] = nudges.iter().map(Nudge::to_json).collect();
+        }
+        if let Some(seed) = &self.seed {
+            doc["seed"] = serde_json::json!(seed_to_hex(seed));
+        }
+        doc["grade"] = serde_json::json!(grade(&self.final_state).0.to_string());
+        doc["final"]["hash"] = serde_json::json!(self.palette_hash());
+        doc["parameters"] = self.parameters_json();
+        doc["parameters"]["hash"] = serde_json::json!(self.config_hash());
+        doc
+    }
+
+    /// Everything needed to rerun the optimization, beyond the starting
+    /// palette.
+    fn parameters_json(&self) -> serde_json::Value {
+        let state = &self.start_state;
+        serde_json::json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "args": self.cli_args(),
+            "weights": self.weights,
+            "targets": hex_colors(&state.target_fg_colors),
+            "distance_metric": "CIEDE2000",
+            "cooling": {
+                "initial_temperature": State::INITIAL_TEMPERATURE,
+                "rate": State::COOLING_RATE,
+                "cutoff": State::CUTOFF,
+            },
+            "restarts": self.n_restarts,
+            "best_restart": self.restart,
+            "budget_secs": self.budget.map(|b| b.as_secs_f32()),
+            "max_iterations": self.config.max_iterations,
+            "wiggle": self.config.wiggle,
+            "anneal_wiggle": self.config.anneal_wiggle,
+            "perturbation": self.config.perturbation.text(),
+            "shuffle": self.config.shuffle_order,
+            "phases": self.config.phases.text(),
+            "phase_rounds": self.config.phase_rounds,
+            "acceptance_rule": self.config.acceptance_rule.text(),
+            "candidates": hex_colors(&self.config.candidates),
+            "cvd_severity": state.cvd_severity,
+            "luma": state.luma.text(),
+            "distance_objective": state.distance_objective.text(),
+            "keep_hue_order": state.fg_hue_order.is_some(),
+            "hue_band": state.fg_hue_band.map(HueBand::text),
+            "pin_chroma": state.fg_pinned_chroma.iter().any(Option::is_some),
+            "achromatic": state.achromatic_roles().map(BgRole::text).collect::<Vec<_>>(),
+            "contrast_level": state.contrast_level.text(),
+            "contrast_steepness": state.contrast_sigmoid.steepness,
+            "contrast_center": state.contrast_sigmoid.center,
+            "color_space": state.space.text(),
+            "precision": state.precision.text(),
+        })
+    }
+
+    /// Command-line flags that rerun this optimization. The weights don't
+    /// fit on the command line, so `--weights` names a file that should
+    /// hold `parameters.weights` from the JSON report; a starting palette
+    /// or targets other than the built-in ones likewise need `--palette`
+    /// and `--targets` files. Runs bounded by `--budget` depend on timing,
+    /// so they only reproduce approximately.
+    fn cli_args(&self) -> Vec<String> {
+        let state = &self.start_state;
+        let config = &self.config;
+        let mut args: Vec<String> = vec![];
+        let mut push = |flag: &str, value: Option<String>| {
+            args.push(flag.to_string());
+            args.extend(value);
+        };
+        if let Some(seed) = &self.seed {
+            push("--seed", Some(seed_to_hex(seed)));
+        }
+        push("--weights", Some("weights.json".to_string()));
+        push("--restarts", Some(self.n_restarts.to_string()));
+        if let Some(budget) = self.budget {
+            push("--budget", Some(budget.as_secs_f32().to_string()));
+        }
+        if let Some(n) = config.max_iterations {
+            push("--iterations", Some(n.to_string()));
+        }
+        push("--wiggle", Some(config.wiggle.to_string()));
+        if config.anneal_wiggle {
+            push("--anneal-wiggle", None);
+        }
+        push("--perturb", Some(config.perturbation.text().to_string()));
+        if config.shuffle_order {
+            push("--shuffle", None);
+        }
+        push("--phases", Some(config.phases.text().to_string()));
+        push("--phase-rounds", Some(config.phase_rounds.to_string()));
+        push(
+            "--acceptance",
+            Some(config.acceptance_rule.text().to_string()),
+        );
+        if !config.candidates.is_empty() {
+            push(
+                "--candidates",
+                Some(hex_colors(&config.candidates).join(",")),
+            );
+        }
+        push(
+            "--contrast-level",
+            Some(state.contrast_level.text().to_string()),
+        );
+        let sigmoid = state.contrast_sigmoid;
+        if sigmoid != ContrastSigmoid::default() {
+            push("--contrast-steepness", Some(sigmoid.steepness.to_string()));
+            push("--contrast-center", Some(sigmoid.center.to_string()));
+        }
+        push("--color-space", Some(state.space.text().to_string()));
+        if state.precision != Precision::F32 {
+            push("--precision", Some(state.precision.text().to_string()));
+        }
+        for pair in state.protected_pairs.iter() {
+            let value = format!(
+                "{}:{}:{}",
+                pair.fg_index,
+                pair.bg_role.text(),
+                pair.min_ratio
+            );
+            push("--protect", Some(value));
+        }
+        for (i, weight) in state.fg_importance.iter().enumerate() {
+            if *weight != 1. {
+                push("--importance", Some(format!("{i}:{weight}")));
+            }
+        }
+        for (i, text_use) in state.fg_text_use.iter().enumerate() {
+            if let Some(text_use) = text_use {
+                let value = format!("{i}:{}/{}", text_use.size_px, text_use.weight);
+                push("--text-use", Some(value));
+            }
+        }
+        if state.locked[..state.fg_colors.len()].iter().all(|&l| l) {
+            push("--background-only", None);
+        }
+        if state.cvd_severity != 1. {
+            push("--cvd-severity", Some(state.cvd_severity.to_string()));
+        }
+        if state.luma != Luma::default() {
+            push("--luma", Some(state.luma.text().to_string()));
+        }
+        if state.distance_objective != DistanceObjective::Rms {
+            push(
+                "--distance-objective",
+                Some(state.distance_objective.text().to_string()),
+            );
+        }
+        if state.fg_hue_order.is_some() {
+            push("--keep-hue-order", None);
+        }
+        if let Some(band) = state.fg_hue_band {
+            push("--hue-band", Some(band.text().to_string()));
+        }
+        if state.fg_pinned_chroma.iter().any(Option::is_some) {
+            push("--pin-chroma", None);
+        }
+        let achromatic: Vec<&str> = state.achromatic_roles().map(BgRole::text).collect();
+        if !achromatic.is_empty() {
+            push("--achromatic", Some(achromatic.join(",")));
+        }
+        if self.nudges.is_some() {
+            push("--contrast-floor", None);
+        }
+        if self.start_nudges.is_some() {
+            push("--feasible-start", None);
+        }
+        if self.fg_positions.is_some() {
+            push("--sort-output", Some("hue".to_string()));
+        }
+        args
+    }
+}
+
 impl Display for Report {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
+        writeln!(
             f,
-            "Cost: {} (start) → {} (final)\n",
+            "Cost: {} (start) → {} (final)",
             self.start_cost.total(&self.weights),
             self.final_cost.total(&self.weights)
         )?;
-        write!(f, "Cost breakdown:\n")?;
-        write!(f, "{}\n", self.start_cost)?;
-        write!(f, "        ↓\n")?;
-        write!(f, "{}\n", self.final_cost)?;
+        writeln!(f, "Cost breakdown:")?;
+        writeln!(f, "{}", self.start_cost)?;
+        writeln!(f, "        ↓")?;
+        writeln!(f, "{}", self.final_cost)?;
+        writeln!(
+            f,
+            "Improvement: {:.1}%; change by term (weighted):",
+            self.improvement_pct()
+        )?;
+        let start_terms = self.start_cost.weighted_terms(&self.weights);
+        let final_terms = self.final_cost.weighted_terms(&self.weights);
+        for ((name, start), (_, end)) in start_terms.into_iter().zip(final_terms) {
+            writeln!(f, "  {name:<14}{:>+9.2}", end - start)?;
+        }
+        let (term, value) = self.final_cost.dominant_term(&self.weights);
+        writeln!(f, "Dominant cost: {term} ({value:.1})")?;
+        let (d, i, j) = self.final_state.closest_fg_bg_pair();
+        if d.is_finite() {
+            let hex = hex_colors(&[
+                self.final_state.fg_colors[i],
+                self.final_state.bg_colors.into_array()[j],
+            ]);
+            writeln!(
+                f,
+                "Closest fg ↔ bg: {d:.1} ({} on {}; minimum {MIN_BG_DISTANCE})",
+                hex[0], hex[1]
+            )?;
+        }
+        let (grade, inputs) = grade(&self.final_state);
+        writeln!(
+            f,
+            "Grade: {grade} ({:.0}% of fg ↔ bg pairs pass {}; closest pair {:.1} apart \
+             under CVD, {:.1} L* apart in grayscale)",
+            100. * inputs.contrast_pass_fraction,
+            self.final_state.contrast_level.text().to_uppercase(),
+            inputs.min_cvd_distance,
+            inputs.min_gray_distance
+        )?;
+        let mut fg_distances = vec![];
+        write!(f, "Closest fg pair by vision:")?;
+        for v in Vision::all() {
+            let seen: Vec<Lch> = self
+                .final_state
+                .fg_colors
+                .iter()
+                .map(|c| {
+                    to_lch(
+                        simulate_vision(*c, v, self.final_state.luma, self.final_state.space),
+                        self.final_state.space,
+                    )
+                })
+                .collect();
+            pairwise_distances(&seen, &mut fg_distances);
+            if let Some(stats) = distance_stats(&fg_distances) {
+                let flag = if stats.min < DeltaE::PERCEPTIBLE {
+                    " (confusable)"
+                } else {
+                    ""
+                };
+                write!(f, "\n  {}: {:.1}{flag}", v.text(), stats.min)?;
+            }
+        }
+        writeln!(f)?;
+        pairwise_distances(&self.final_state.fg_lch, &mut fg_distances);
+        if let Some(stats) = distance_stats(&fg_distances) {
+            writeln!(
+                f,
+                "fg distances: min {:.1}, mean {:.1} (σ {:.1}), max {:.1}",
+                stats.min, stats.mean, stats.stddev, stats.max
+            )?;
+        }
+        let lightness: Vec<f32> = self.final_state.fg_lch.iter().map(|lch| lch.l).collect();
+        if !lightness.is_empty() {
+            let (min, max) = lightness
+                .iter()
+                .fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), &l| {
+                    (lo.min(l), hi.max(l))
+                });
+            writeln!(
+                f,
+                "fg lightness: L* {min:.1} to {max:.1} (range {:.1})",
+                max - min
+            )?;
+        }
+        for (pair, ratio) in self.final_state.violated_protected_pairs() {
+            let fg = self.final_state.fg_colors[pair.fg_index];
+            let (_, fg_lighter) = contrast_directional(
+                fg,
+                self.final_state.bg_colors.get(pair.bg_role),
+                self.final_state.space,
+            );
+            writeln!(
+                f,
+                "Protected pair violated: {} on {}: {} < {:.2}:1 ({} it to fix)",
+                hex_colors(&[fg])[0],
+                pair.bg_role.text(),
+                ratio,
+                pair.min_ratio,
+                if fg_lighter { "lighten" } else { "darken" }
+            )?;
+        }
+        for (fg, text_use) in self
+            .final_state
+            .fg_colors
+            .iter()
+            .zip(&self.final_state.fg_text_use)
+        {
+            let Some(text_use) = text_use else {
+                continue;
+            };
+            let (lc, role) = BackgroundColors::ACTIVE
+                .into_iter()
+                .map(|role| {
+                    let bg = self.final_state.bg_colors.get(role);
+                    (
+                        apca::lightness_contrast(*fg, bg, self.final_state.space).abs(),
+                        role,
+                    )
+                })
+                .min_by(|(a, _), (b, _)| cmp_nan_last(*a, *b))
+                .expect("There is always an active background");
+            let required = text_use.required_lc();
+            writeln!(
+                f,
+                "Text use {text_use} for {}: Lc {lc:.1} on {} (needs {required}){}",
+                hex_colors(&[*fg])[0],
+                role.text(),
+                if lc < required { " (too low)" } else { "" }
+            )?;
+        }
+        for i in 0..self.final_state.fg_colors.len() {
+            if let Some((role, ratio)) = self.final_state.worst_protected_background(i) {
+                writeln!(
+                    f,
+                    "Worst protected background for {}: {} ({})",
+                    hex_colors(&[self.final_state.fg_colors[i]])[0],
+                    role.text(),
+                    ratio
+                )?;
+            }
+        }
         let secs = self.duration.as_secs_f32();
-        write!(
+        writeln!(
             f,
-            "Time: {:.2}s for {} iterations ({} iters/sec)\n",
+            "Time: {:.2}s for {} iterations ({} iters/sec)",
             secs,
             self.n_iterations,
             (self.n_iterations as f32) / secs
         )?;
+        if let Some(budget) = self.budget {
+            writeln!(
+                f,
+                "Budget: {:.2}s ({} iterations fit)",
+                budget.as_secs_f32(),
+                self.n_iterations
+            )?;
+        }
+        match self.stop_reason {
+            StopReason::Cutoff => writeln!(f, "Stopped by: temperature cutoff")?,
+            StopReason::Budget => writeln!(f, "Stopped by: time budget")?,
+            StopReason::IterationCap => writeln!(f, "Stopped by: iteration cap")?,
+        }
+        if let Some(seed) = &self.seed {
+            writeln!(f, "Seed: {}", seed_to_hex(seed))?;
+        }
+        writeln!(
+            f,
+            "Palette hash: {}; config hash: {}",
+            self.palette_hash(),
+            self.config_hash()
+        )?;
+        writeln!(
+            f,
+            "Version {}; cooling from {} by {} down to {}; distance CIEDE2000",
+            env!("CARGO_PKG_VERSION"),
+            State::INITIAL_TEMPERATURE,
+            State::COOLING_RATE,
+            State::CUTOFF
+        )?;
+        writeln!(
+            f,
+            "Rerun with (weights.json holding the weights): {}",
+            self.cli_args().join(" ")
+        )?;
+        writeln!(
+            f,
+            "Wiggle: {} in {}{}",
+            self.config.wiggle,
+            self.config.perturbation.text(),
+            if self.config.anneal_wiggle {
+                " (annealed)"
+            } else {
+                ""
+            }
+        )?;
+        if !self.config.candidates.is_empty() {
+            writeln!(
+                f,
+                "Candidates: foregrounds chosen from {} colors",
+                self.config.candidates.len()
+            )?;
+        }
+        match self.config.phases {
+            Phases::Joint => writeln!(f, "Phases: joint")?,
+            Phases::Alternating => writeln!(
+                f,
+                "Phases: alternating, {} round(s) of background then foreground",
+                self.config.phase_rounds
+            )?,
+        }
+        if self.n_restarts > 1 {
+            writeln!(
+                f,
+                "Restarts: best of {} was #{}",
+                self.n_restarts, self.restart
+            )?;
+        }
+        write!(
+            f,
+            "Acceptance ({}): {:.1}% overall; by temperature:",
+            self.config.acceptance_rule.text(),
+            AcceptanceStats::rate(self.acceptance.accepted, self.acceptance.proposed)
+        )?;
+        for (i, (accepted, proposed)) in self.acceptance.by_decade.iter().enumerate() {
+            let upper = State::INITIAL_TEMPERATURE / 10f32.powi(i as i32);
+            write!(
+                f,
+                "  ≤{:e}: {:.1}%",
+                upper,
+                AcceptanceStats::rate(*accepted, *proposed)
+            )?;
+        }
+        writeln!(f)?;
+        let clipped_pct = AcceptanceStats::rate(self.acceptance.clipped, self.acceptance.proposed);
+        writeln!(
+            f,
+            "Clipping: {clipped_pct:.1}% of moves left sRGB, {:.2} clipped in total",
+            self.acceptance.total_clipping
+        )?;
+        if clipped_pct > Self::CLIPPING_WARN_PCT {
+            writeln!(
+                f,
+                "Warning: many moves are clipped to the gamut; try a smaller --wiggle"
+            )?;
+        }
         write!(
             f,
             "Background colors:\n  {:?}\n",
@@ -84,13 +1044,86 @@ impl Display for Report {
         write!(
             f,
             "Foreground colors:\n  {:?}\n",
-            hex_colors(&self.start_state.fg_colors)
+            brand_named_colors(&self.start_state.fg_colors, self.final_state.space)
         )?;
         write!(
             f,
             "        ↓\n  {:?}\n",
-            hex_colors(&self.final_state.fg_colors)
-        )
+            brand_named_colors(&self.final_state.fg_colors, self.final_state.space)
+        )?;
+        if let Some(nudges) = &self.start_nudges {
+            Nudge::write_all(f, "Feasible start", nudges, "the main background")?;
+        }
+        if let Some(nudges) = &self.nudges {
+            Nudge::write_all(f, "Contrast floor", nudges, "every background")?;
+        }
+        if let Some(positions) = &self.fg_positions {
+            writeln!(f, "Sorted by hue; new position of each input color:")?;
+            writeln!(f, "  {positions:?}")?;
+        }
+        // Pair colors by input position, even if the output was sorted.
+        let after: Vec<Color> = match &self.fg_positions {
+            Some(positions) => positions
+                .iter()
+                .map(|&i| self.final_state.fg_colors[i])
+                .collect(),
+            None => self.final_state.fg_colors.clone(),
+        };
+        writeln!(f, "Foreground movement (ΔE):")?;
+        for (i, (before, after, d)) in
+            palette_diff(&self.start_state.fg_colors, &after, self.final_state.space)
+                .into_iter()
+                .enumerate()
+        {
+            let hex = hex_colors(&[before, after]);
+            writeln!(f, "  color {i} moved {d:.1} ({} → {})", hex[0], hex[1])?;
+        }
+        let min_ratio = required_ratio(ContrastNeed::Text, self.final_state.contrast_level);
+        writeln!(f, "Contrast on white and black (text needs {min_ratio}:1):")?;
+        for (i, c) in self.final_state.fg_colors.iter().enumerate() {
+            let (on_white, on_black) = contrast_on_extremes(*c, self.final_state.space);
+            let usable = match (on_white >= min_ratio, on_black >= min_ratio) {
+                (true, true) => "text on either",
+                (true, false) => "text on white",
+                (false, true) => "text on black",
+                (false, false) => "not for text",
+            };
+            writeln!(
+                f,
+                "  color {i} {}: {on_white:.2}:1 on white, {on_black:.2}:1 on black; {usable}",
+                hex_colors(&[*c])[0]
+            )?;
+        }
+        writeln!(f, "Text on each color used as a fill:")?;
+        for (i, c) in self.final_state.fg_colors.iter().enumerate() {
+            let (text, ratio) =
+                best_text_color(*c, self.final_state.contrast_level, self.final_state.space);
+            writeln!(
+                f,
+                "  color {i} {}: {} text, {ratio}{}",
+                hex_colors(&[*c])[0],
+                text.text(),
+                match ratio.failed_criterion() {
+                    Some(sc) => format!(" (too low for body text; fails SC {})", sc.id),
+                    None => String::new(),
+                }
+            )?;
+        }
+        writeln!(f, "Gamut headroom (LCH chroma that could still be added):")?;
+        for (i, c) in self.final_state.fg_colors.iter().enumerate() {
+            let headroom = gamut_headroom(*c, self.final_state.space);
+            writeln!(
+                f,
+                "  color {i} {}: {headroom:.1}{}",
+                hex_colors(&[*c])[0],
+                if headroom < Self::FRAGILE_HEADROOM {
+                    " (at the gamut edge; moves will often clip)"
+                } else {
+                    ""
+                }
+            )?;
+        }
+        Ok(())
     }
 }
 
@@ -98,179 +1131,913 @@ impl State {
     const INITIAL_TEMPERATURE: f32 = 1000.;
     const COOLING_RATE: f32 = 0.99;
     const CUTOFF: f32 = 0.0001;
+    /// How many of the nearest candidate colors a move may jump to.
+    const CANDIDATE_NEIGHBORS: usize = 4;
+
+    /// Scores importance-weighted distances against the ideal of 100 with
+    /// the configured [`DistanceObjective`].
+    fn distance_score(&self, distances: &[f32], importance: impl Iterator<Item = f32>) -> f32 {
+        match self.distance_objective {
+            DistanceObjective::Rms => {
+                weighted_root_mean_square_distance(100., distances, importance, self.precision)
+            }
+            DistanceObjective::Maximin => weighted_max_shortfall(100., distances, importance),
+        }
+    }
 
     fn distance_cost(&self, bufs: &mut ScratchBuffers, v: Vision) -> ScaledCost {
-        // Map to bretter-function transformed colors first.
-        bufs.bg_colors.clear();
-        bufs.fg_colors.clear();
+        debug_assert!(self.lch_cache_is_fresh());
+        let ScratchBuffers {
+            bg_lch,
+            fg_lch,
+            bg_to_bg,
+            bg_to_fg,
+            fg_to_fg,
+            ..
+        } = bufs;
 
-        bufs.bg_colors.extend(
-            self.bg_colors
-                .into_array()
-                .into_iter()
-                .map(|c| brettel_function(c, v)),
-        );
-        bufs.fg_colors
-            .extend(self.fg_colors.iter().map(|c| brettel_function(*c, v)));
+        // Map to bretter-function transformed colors first. Default vision
+        // is the identity, so the cached conversions can be used directly.
+        let (bg_lch, fg_lch): (&[Lch], &[Lch]) = match v {
+            Vision::Default => (&self.bg_lch, &self.fg_lch),
+            _ => {
+                bg_lch.clear();
+                fg_lch.clear();
+                bg_lch.extend(self.bg_colors.into_array().into_iter().map(|c| {
+                    to_lch(
+                        brettel_function_at(c, v, self.cvd_severity, self.luma, self.space),
+                        self.space,
+                    )
+                }));
+                fg_lch.extend(self.fg_colors.iter().map(|c| {
+                    to_lch(
+                        brettel_function_at(*c, v, self.cvd_severity, self.luma, self.space),
+                        self.space,
+                    )
+                }));
+                (bg_lch, fg_lch)
+            }
+        };
 
         // Compute distances and scores if needed.
         let mut bg_bg_score: f32 = 0.;
         if self.weights.distance_bg_bg_weight != 0. {
-            pairwise_distances(&bufs.bg_colors, &mut bufs.bg_to_bg);
-            bg_bg_score = root_mean_square_distance(100., &bufs.bg_to_bg);
+            pairwise_distances(bg_lch, bg_to_bg);
+            bg_bg_score = match self.distance_objective {
+                DistanceObjective::Rms => root_mean_square_distance(100., bg_to_bg, self.precision),
+                DistanceObjective::Maximin => {
+                    weighted_max_shortfall(100., bg_to_bg, std::iter::repeat(1.))
+                }
+            };
         }
 
         let mut bg_fg_score: f32 = 0.;
         if self.weights.distance_bg_fg_weight != 0. {
-            pairwise_distances_2(&bufs.bg_colors, &bufs.fg_colors, &mut bufs.bg_to_fg);
-            bg_fg_score = root_mean_square_distance(100., &bufs.bg_to_fg);
+            pairwise_distances_2(bg_lch, fg_lch, bg_to_fg);
+            let importance = bg_lch
+                .iter()
+                .flat_map(|_| self.fg_importance.iter().copied());
+            bg_fg_score = self.distance_score(bg_to_fg, importance);
         }
 
+        // With fewer than two foregrounds there's nothing to separate, so
+        // the score stays at its best rather than averaging no pairs.
         let mut fg_fg_score: f32 = 0.;
-        if self.weights.distance_fg_fg_weight != 0. {
-            pairwise_distances(&bufs.fg_colors, &mut bufs.fg_to_fg);
-            fg_fg_score = root_mean_square_distance(100., &bufs.fg_to_fg);
+        if self.weights.distance_fg_fg_weight != 0. && fg_lch.len() >= 2 {
+            pairwise_distances(fg_lch, fg_to_fg);
+            let w = &self.fg_importance;
+            let importance = (0..w.len()).flat_map(|i| (i + 1..w.len()).map(move |j| w[i] * w[j]));
+            fg_fg_score = self.distance_score(fg_to_fg, importance);
         }
 
-        ScaledCost::new(
-            bg_bg_score * self.weights.distance_bg_bg_weight
-                + bg_fg_score * self.weights.distance_bg_fg_weight
-                + fg_fg_score * self.weights.distance_fg_fg_weight,
-        )
+        ScaledCost::weighted_sum(&[
+            (
+                ScaledCost::new(bg_bg_score),
+                self.weights.distance_bg_bg_weight,
+            ),
+            (
+                ScaledCost::new(bg_fg_score),
+                self.weights.distance_bg_fg_weight,
+            ),
+            (
+                ScaledCost::new(fg_fg_score),
+                self.weights.distance_fg_fg_weight,
+            ),
+        ])
     }
 
-    fn target_cost(&self, bufs: &mut ScratchBuffers) -> ScaledCost {
+    fn target_cost(&self) -> ScaledCost {
+        if self.weights.target_weight == 0. {
+            return ScaledCost::new(0.);
+        }
+        debug_assert!(self.target_cache_is_fresh());
         let mut target_bg_score: f32 = 0.;
         if self.weights.target_bg_weight != 0. {
-            bufs.bg_to_bg.clear();
-            for current in self.bg_color_array.iter() {
-                let closest = get_closest_color(*current, &self.target_bg_colors);
-                bufs.bg_to_bg.push(distance(*current, closest));
-            }
-            target_bg_score = root_mean_square(&bufs.bg_to_bg);
+            target_bg_score = root_mean_square(&self.bg_target_distances, self.precision);
         }
 
         let mut target_fg_score: f32 = 0.;
         if self.weights.target_fg_weight != 0. {
-            bufs.fg_to_fg.clear();
-            for current in self.fg_colors.iter() {
-                let closest = get_closest_color(*current, &self.target_fg_colors);
-                bufs.fg_to_fg.push(distance(*current, closest));
-            }
-            target_fg_score = root_mean_square(&bufs.fg_to_fg);
+            target_fg_score = weighted_root_mean_square(
+                &self.fg_target_distances,
+                self.fg_importance.iter().copied(),
+                self.precision,
+            );
         }
 
-        ScaledCost::new(
-            target_bg_score * self.weights.target_bg_weight
-                + target_fg_score * self.weights.target_fg_weight,
-        )
+        ScaledCost::weighted_sum(&[
+            (
+                ScaledCost::new(target_bg_score),
+                self.weights.target_bg_weight,
+            ),
+            (
+                ScaledCost::new(target_fg_score),
+                self.weights.target_fg_weight,
+            ),
+        ])
     }
 
     fn contrast_cost(&self, bufs: &mut ScratchBuffers) -> ScaledCost {
         let mut contrast_bg_bg_score: f32 = 0.;
         if self.weights.contrast_bg_bg_weight != 0. {
-            contrast_bg_bg_score = self.bg_colors.contrast_cost().value();
+            contrast_bg_bg_score = self
+                .bg_colors
+                .contrast_cost(
+                    self.contrast_level,
+                    self.contrast_sigmoid,
+                    self.space,
+                    self.precision,
+                )
+                .value();
         }
 
         let mut contrast_bg_fg_score: f32 = 0.;
         if self.weights.contrast_bg_fg_weight != 0. {
+            debug_assert!(self.contrast_cache_is_fresh());
             bufs.bg_to_fg.clear();
-            for bg in self.bg_color_array.iter() {
-                for fg in self.fg_colors.iter() {
-                    bufs.bg_to_fg.push(
-                        ContrastRatio::for_pair(*bg, *fg, ContrastNeed::Text)
-                            .cost()
-                            .value(),
-                    );
+            let rows = || self.fg_contrast_costs.iter().zip(&self.fg_text_use);
+            for j in 0..self.bg_color_array.len() {
+                for (row, text_use) in rows() {
+                    // Text with a declared use is held to its APCA Lc
+                    // instead, on every active background, below.
+                    if text_use.is_none() {
+                        bufs.bg_to_fg.push(row[j]);
+                    }
+                }
+            }
+            for (row, text_use) in rows() {
+                if text_use.is_some() {
+                    bufs.bg_to_fg.extend(row);
                 }
             }
-            contrast_bg_fg_score = root_mean_square(&bufs.bg_to_fg);
+            contrast_bg_fg_score = root_mean_square(&bufs.bg_to_fg, self.precision);
         }
 
-        ScaledCost::new(
-            contrast_bg_bg_score * self.weights.contrast_bg_bg_weight
-                + contrast_bg_fg_score * self.weights.contrast_bg_fg_weight,
-        )
+        ScaledCost::weighted_sum(&[
+            (
+                ScaledCost::new(contrast_bg_bg_score),
+                self.weights.contrast_bg_bg_weight,
+            ),
+            (
+                ScaledCost::new(contrast_bg_fg_score),
+                self.weights.contrast_bg_fg_weight,
+            ),
+        ])
+    }
+
+    /// The smallest distance between a foreground and an active background
+    /// color, with the (fg, bg) indices of that pair. Infinite if there are
+    /// no foreground colors.
+    fn closest_fg_bg_pair(&self) -> (f32, usize, usize) {
+        let mut closest = (f32::INFINITY, 0, 0);
+        for (j, bg) in self.bg_lch.iter().enumerate() {
+            for (i, fg) in self.fg_lch.iter().enumerate() {
+                let d = distance_lch(fg, bg);
+                if d < closest.0 {
+                    closest = (d, i, j);
+                }
+            }
+        }
+        closest
+    }
+
+    /// Protected pairs below their minimum ratio, with the ratio achieved.
+    fn violated_protected_pairs(&self) -> Vec<(ProtectedPair, ContrastRatio)> {
+        self.protected_pairs
+            .iter()
+            .filter_map(|pair| {
+                let ratio = ContrastRatio::for_pair(
+                    self.fg_colors[pair.fg_index],
+                    self.bg_colors.get(pair.bg_role),
+                    ContrastNeed::Text,
+                    self.contrast_level,
+                    self.space,
+                );
+                (ratio.value() < pair.min_ratio).then_some((*pair, ratio))
+            })
+            .collect()
+    }
+
+    /// How many hard constraints the palette breaks: foregrounds below the
+    /// text minimum (or their text use's Lc) on an active background,
+    /// violated protected pairs, and foreground pairs too close to tell
+    /// apart.
+    fn validate(&self) -> usize {
+        let mut n_broken = 0;
+        for i in 0..self.fg_colors.len() {
+            for bg in self.bg_colors.into_array() {
+                n_broken += !self.text_contrast(i, bg).passes() as usize;
+            }
+        }
+        n_broken += self.violated_protected_pairs().len();
+        let mut distances = vec![];
+        pairwise_distances(&self.fg_lch, &mut distances);
+        n_broken += distances
+            .iter()
+            .filter(|&&d| d < DeltaE::PERCEPTIBLE)
+            .count();
+        n_broken
+    }
+
+    /// How readable foreground `i` is as text on `bg`, measured the way its
+    /// contrast is scored: by the APCA Lc if it has a text use, and by the
+    /// WCAG ratio otherwise.
+    fn text_contrast(&self, i: usize, bg: Color) -> TextContrast {
+        let fg = self.fg_colors[i];
+        match self.fg_text_use[i] {
+            Some(text_use) => TextContrast::Apca {
+                lc: apca::lightness_contrast(fg, bg, self.space).abs(),
+                text_use,
+            },
+            None => TextContrast::Wcag(ContrastRatio::for_pair(
+                fg,
+                bg,
+                ContrastNeed::Text,
+                self.contrast_level,
+                self.space,
+            )),
+        }
+    }
+
+    /// The foreground closest to any other, breaking ties by the mean
+    /// distance to the rest, i.e. the one whose removal costs the palette
+    /// the least distinctness. `None` with fewer than two foregrounds.
+    fn least_distinct_fg(&self) -> Option<usize> {
+        let n = self.fg_colors.len();
+        (0..n)
+            .filter(|_| n >= 2)
+            .map(|i| {
+                let others: Vec<f32> = (0..n)
+                    .filter(|&j| j != i)
+                    .map(|j| distance_lch(&self.fg_lch[i], &self.fg_lch[j]))
+                    .collect();
+                let nearest = others.iter().copied().fold(f32::INFINITY, f32::min);
+                let mean = others.iter().sum::<f32>() / others.len() as f32;
+                (i, nearest, mean)
+            })
+            .min_by(|a, b| cmp_nan_last(a.1, b.1).then(cmp_nan_last(a.2, b.2)))
+            .map(|(i, _, _)| i)
+    }
+
+    /// The lowest contrast of any foreground on any active background, or
+    /// `None` without foregrounds.
+    fn worst_contrast(&self) -> Option<ContrastRatio> {
+        self.fg_colors
+            .iter()
+            .flat_map(|fg| {
+                self.bg_colors.into_array().map(|bg| {
+                    ContrastRatio::for_pair(
+                        *fg,
+                        bg,
+                        ContrastNeed::Text,
+                        self.contrast_level,
+                        self.space,
+                    )
+                })
+            })
+            .min_by(|a, b| cmp_nan_last(a.value(), b.value()))
+    }
+
+    /// Among the protected pairs of foreground `fg_index`, the background it
+    /// has the least contrast against.
+    fn worst_protected_background(&self, fg_index: usize) -> Option<(BgRole, ContrastRatio)> {
+        self.protected_pairs
+            .iter()
+            .filter(|pair| pair.fg_index == fg_index)
+            .map(|pair| {
+                let ratio = ContrastRatio::for_pair(
+                    self.fg_colors[fg_index],
+                    self.bg_colors.get(pair.bg_role),
+                    ContrastNeed::Text,
+                    self.contrast_level,
+                    self.space,
+                );
+                (pair.bg_role, ratio)
+            })
+            .min_by(|(_, a), (_, b)| cmp_nan_last(a.value(), b.value()))
     }
 
     fn total_cost(&self, bufs: &mut ScratchBuffers) -> TotalCost {
         use Vision::*;
 
-        return TotalCost {
-            contrast_cost: self.contrast_cost(bufs).value(),
+        // The CVD passes are the most expensive part, so skip them outright
+        // (reporting 0) for terms that don't count.
+        let mut cvd_cost = |v: Vision, weight: f32| {
+            if weight == 0. {
+                return 0.;
+            }
+            self.distance_cost(bufs, v).value()
+        };
+        let w = &self.weights;
+        let protanopia_cost = cvd_cost(Protanopia, w.protanopia_weight);
+        let deuteranopia_cost = cvd_cost(Deuteranopia, w.deuteranopia_weight);
+        let tritanopia_cost = cvd_cost(Tritanopia, w.tritanopia_weight);
+        TotalCost {
+            contrast_cost: self.contrast_cost(bufs).value()
+                + ProtectedPair::PENALTY * self.violated_protected_pairs().len() as f32,
             distance_cost: self.distance_cost(bufs, Default).value(),
             // Range calculation has to happen after the above, so distance values are filled.
             range_cost: max_minus_min(&bufs.fg_to_fg),
-            target_cost: self.target_cost(bufs).value(),
-            protanopia_cost: self.distance_cost(bufs, Protanopia).value(),
-            deuteranopia_cost: self.distance_cost(bufs, Deuteranopia).value(),
-            tritanopia_cost: self.distance_cost(bufs, Tritanopia).value(),
-        };
+            target_cost: self.target_cost().value(),
+            protanopia_cost,
+            deuteranopia_cost,
+            tritanopia_cost,
+            bg_clearance_cost: bg_clearance_cost(self.closest_fg_bg_pair().0).value(),
+        }
     }
 
-    fn new(bg_colors: BackgroundColors, target_fg_colors: Vec<Color>, weights: Weights) -> Self {
-        State {
+    /// Starts from `fg_colors`, which the target term pulls towards the
+    /// nearest of `target_fg_colors`. These are usually the same colors,
+    /// but the targets can be a separate reference palette of any size.
+    fn new(
+        bg_colors: BackgroundColors,
+        fg_colors: Vec<Color>,
+        target_fg_colors: Vec<Color>,
+        weights: Weights,
+    ) -> Self {
+        let locked = vec![false; fg_colors.len() + BackgroundColors::MODIFIABLE_COUNT];
+        let target_bg_colors = bg_colors.updateable_array().to_vec();
+        let fg_importance = vec![1.; fg_colors.len()];
+        let fg_text_use = vec![None; fg_colors.len()];
+        let fg_pinned_chroma = vec![None; fg_colors.len()];
+        let mut state = State {
             bg_colors,
             bg_color_array: bg_colors.updateable_array().to_vec(),
-            fg_colors: target_fg_colors.clone(),
-            target_bg_colors: bg_colors.updateable_array().to_vec(),
+            fg_colors,
+            target_bg_colors,
             target_fg_colors,
+            protected_pairs: vec![],
+            contrast_level: ContrastLevel::default(),
+            contrast_sigmoid: ContrastSigmoid::default(),
+            cvd_severity: 1.,
+            luma: Luma::default(),
+            space: ColorSpace::default(),
+            precision: Precision::default(),
+            distance_objective: DistanceObjective::Rms,
+            fg_importance,
+            fg_text_use,
+            fg_pinned_chroma,
+            locked,
+            fg_hue_order: None,
+            fg_hue_band: None,
+            bg_achromatic: vec![false; BackgroundColors::MODIFIABLE_COUNT],
+            bg_lch: vec![],
+            fg_lch: vec![],
+            bg_target_distances: vec![],
+            fg_target_distances: vec![],
+            fg_contrast_costs: vec![],
             weights,
-        }
+        };
+        state.refresh_caches();
+        state
     }
 
-    fn sync_bg_slot(&mut self, mut i: usize) {
-        if i < self.fg_colors.len() {
-            return;
-        }
-        i = i - self.fg_colors.len();
-        let mut a = self.bg_colors.updateable_array();
-        a[i] = self.bg_color_array[i];
-        self.bg_colors.update(a);
+    /// Switches the RGB space the colors are read in, keeping their channel
+    /// values; see [`ColorSpace`].
+    fn set_color_space(&mut self, space: ColorSpace) {
+        self.space = space;
+        self.refresh_caches();
     }
 
-    fn color_slot(&mut self, i: usize) -> &mut Color {
-        if i < self.fg_colors.len() {
-            &mut self.fg_colors[i]
-        } else {
-            &mut self.bg_color_array[i - self.fg_colors.len()]
+    /// Recomputes every cached conversion and cost from the colors.
+    fn refresh_caches(&mut self) {
+        let space = self.space;
+        self.bg_lch = self
+            .bg_colors
+            .into_array()
+            .map(|c| to_lch(c, space))
+            .to_vec();
+        self.fg_lch = self.fg_colors.iter().map(|c| to_lch(*c, space)).collect();
+        let target_distances = |colors: &[Color], targets: &[Color]| {
+            colors
+                .iter()
+                .map(|c| Self::target_distance(&self.weights, *c, targets, space))
+                .collect()
+        };
+        self.bg_target_distances = target_distances(&self.bg_color_array, &self.target_bg_colors);
+        self.fg_target_distances = target_distances(&self.fg_colors, &self.target_fg_colors);
+        self.refresh_contrast_cache();
+    }
+
+    /// Fills `row` with the contrast costs of fg color `i`, as cached in
+    /// fg_contrast_costs.
+    fn fg_contrast_row(&self, i: usize, row: &mut Vec<f32>) {
+        let fg = self.fg_colors[i];
+        row.clear();
+        match self.fg_text_use[i] {
+            None => row.extend(self.bg_color_array.iter().map(|bg| {
+                ContrastRatio::for_pair(
+                    *bg,
+                    fg,
+                    ContrastNeed::Text,
+                    self.contrast_level,
+                    self.space,
+                )
+                .cost(self.contrast_sigmoid)
+                .value()
+            })),
+            Some(text_use) => row.extend(
+                self.bg_colors
+                    .into_array()
+                    .map(|bg| text_use.cost(fg, bg, self.space).value()),
+            ),
         }
     }
 
-    fn optimize(&mut self, rng: &mut Rng) -> Report {
-        let mut bufs = ScratchBuffers::default();
-        let start_cost = self.total_cost(&mut bufs);
-        let start_state = self.clone();
-        let mut old_cost = start_cost.clone();
+    fn sync_contrast_row(&mut self, i: usize) {
+        let mut row = std::mem::take(&mut self.fg_contrast_costs[i]);
+        self.fg_contrast_row(i, &mut row);
+        self.fg_contrast_costs[i] = row;
+    }
 
-        let mut temperature = Self::INITIAL_TEMPERATURE;
+    /// Recomputes every cached contrast cost.
+    fn refresh_contrast_cache(&mut self) {
+        self.fg_contrast_costs.resize(self.fg_colors.len(), vec![]);
+        let ratios = contrast_matrix(
+            &self.fg_colors,
+            &self.bg_color_array,
+            ContrastNeed::Text,
+            self.contrast_level,
+            self.space,
+        );
+        let n_bg = self.bg_color_array.len();
+        for i in 0..self.fg_colors.len() {
+            if self.fg_text_use[i].is_some() {
+                self.sync_contrast_row(i);
+                continue;
+            }
+            let row = &mut self.fg_contrast_costs[i];
+            row.clear();
+            row.extend(
+                ratios[i * n_bg..(i + 1) * n_bg]
+                    .iter()
+                    .map(|r| r.cost(self.contrast_sigmoid).value()),
+            );
+        }
+    }
+
+    fn contrast_cache_is_fresh(&self) -> bool {
+        let mut row = vec![];
+        self.fg_contrast_costs.len() == self.fg_colors.len()
+            && (0..self.fg_colors.len()).all(|i| {
+                self.fg_contrast_row(i, &mut row);
+                row == self.fg_contrast_costs[i]
+            })
+    }
+
+    /// Distance from `c` to the closest of `targets`; zero if there are no
+    /// targets to stay close to, or the target term is off.
+    fn target_distance(weights: &Weights, c: Color, targets: &[Color], space: ColorSpace) -> f32 {
+        if weights.target_weight == 0. {
+            return 0.;
+        }
+        get_closest_color(c, targets, space).map_or(0., |t| distance(c, t, space))
+    }
+
+    fn target_cache_is_fresh(&self) -> bool {
+        let fresh = |colors: &[Color], targets: &[Color], cached: &[f32]| {
+            colors.len() == cached.len()
+                && colors.iter().zip(cached).all(|(c, d)| {
+                    Self::target_distance(&self.weights, *c, targets, self.space) == *d
+                })
+        };
+        fresh(
+            &self.bg_color_array,
+            &self.target_bg_colors,
+            &self.bg_target_distances,
+        ) && fresh(
+            &self.fg_colors,
+            &self.target_fg_colors,
+            &self.fg_target_distances,
+        )
+    }
+
+    fn lch_cache_is_fresh(&self) -> bool {
+        self.bg_lch
+            .iter()
+            .zip(self.bg_colors.into_array())
+            .chain(self.fg_lch.iter().zip(self.fg_colors.iter().copied()))
+            .all(|(lch, c)| *lch == to_lch(c, self.space))
+    }
+
+    /// Reorders the foreground colors so that position `i` holds the color
+    /// previously at `order[i]`, keeping per-color fields consistent.
+    /// Returns the new position of each color, indexed by old position.
+    fn permute_fg(&mut self, order: &[usize]) -> Vec<usize> {
+        assert_eq!(order.len(), self.fg_colors.len());
+        let mut positions = vec![0; order.len()];
+        for (new, &old) in order.iter().enumerate() {
+            positions[old] = new;
+        }
+        self.fg_colors = order.iter().map(|&i| self.fg_colors[i]).collect();
+        self.fg_lch = order.iter().map(|&i| self.fg_lch[i]).collect();
+        self.fg_importance = order.iter().map(|&i| self.fg_importance[i]).collect();
+        self.fg_text_use = order.iter().map(|&i| self.fg_text_use[i]).collect();
+        self.fg_pinned_chroma = order.iter().map(|&i| self.fg_pinned_chroma[i]).collect();
+        self.fg_contrast_costs = order
+            .iter()
+            .map(|&i| std::mem::take(&mut self.fg_contrast_costs[i]))
+            .collect();
+        self.fg_target_distances = order.iter().map(|&i| self.fg_target_distances[i]).collect();
+        let fg_locks: Vec<bool> = order.iter().map(|&i| self.locked[i]).collect();
+        self.locked[..order.len()].copy_from_slice(&fg_locks);
+        for pair in self.protected_pairs.iter_mut() {
+            pair.fg_index = positions[pair.fg_index];
+        }
+        for k in self.fg_hue_order.iter_mut().flatten() {
+            *k = positions[*k];
+        }
+        positions
+    }
+
+    /// Propagates a change to color_slot(i) into the derived fields.
+    fn sync_slot(&mut self, i: usize) {
+        self.sync_bg_slot(i);
+        if i < self.fg_colors.len() {
+            self.fg_lch[i] = to_lch(self.fg_colors[i], self.space);
+            self.fg_target_distances[i] = Self::target_distance(
+                &self.weights,
+                self.fg_colors[i],
+                &self.target_fg_colors,
+                self.space,
+            );
+            self.sync_contrast_row(i);
+        } else {
+            let j = i - self.fg_colors.len();
+            self.bg_target_distances[j] = Self::target_distance(
+                &self.weights,
+                self.bg_color_array[j],
+                &self.target_bg_colors,
+                self.space,
+            );
+            for (lch, c) in self.bg_lch.iter_mut().zip(self.bg_colors.into_array()) {
+                *lch = to_lch(c, self.space);
+            }
+            self.refresh_contrast_cache();
+        }
+    }
+
+    fn n_slots(&self) -> usize {
+        self.fg_colors.len() + BackgroundColors::MODIFIABLE_COUNT
+    }
+
+    /// Appends an unlocked foreground color.
+    fn push_fg(&mut self, c: Color) {
+        let i = self.fg_colors.len();
+        self.fg_colors.push(c);
+        self.fg_importance.push(1.);
+        self.fg_text_use.push(None);
+        self.fg_pinned_chroma.push(None);
+        self.fg_lch.push(to_lch(c, self.space));
+        self.fg_target_distances.push(Self::target_distance(
+            &self.weights,
+            c,
+            &self.target_fg_colors,
+            self.space,
+        ));
+        self.fg_contrast_costs.push(vec![]);
+        self.sync_contrast_row(i);
+        self.locked.insert(i, false);
+    }
+
+    /// Removes foreground `i`, along with its protected pairs.
+    fn remove_fg(&mut self, i: usize) {
+        self.fg_colors.remove(i);
+        self.fg_importance.remove(i);
+        self.fg_text_use.remove(i);
+        self.fg_pinned_chroma.remove(i);
+        self.fg_lch.remove(i);
+        self.fg_target_distances.remove(i);
+        self.fg_contrast_costs.remove(i);
+        self.locked.remove(i);
+        let shift = |k: usize| if k > i { k - 1 } else { k };
+        self.protected_pairs.retain(|pair| pair.fg_index != i);
+        for pair in self.protected_pairs.iter_mut() {
+            pair.fg_index = shift(pair.fg_index);
+        }
+        if let Some(order) = &mut self.fg_hue_order {
+            order.retain(|&k| k != i);
+            for k in order.iter_mut() {
+                *k = shift(*k);
+            }
+        }
+    }
+
+    /// Holds all foreground colors fixed so only backgrounds move.
+    fn lock_foreground(&mut self) {
+        for i in 0..self.fg_colors.len() {
+            self.locked[i] = true;
+        }
+    }
+
+    /// Rejects any later move that would reorder the foreground colors by
+    /// LCH hue, e.g. to keep the steps of a sequential palette in order.
+    fn keep_hue_order(&mut self) {
+        let mut order: Vec<usize> = (0..self.fg_colors.len()).collect();
+        let hue = |k: usize| self.fg_lch[k].hue.to_positive_degrees();
+        order.sort_by(|&i, &j| cmp_nan_last(hue(i), hue(j)));
+        self.fg_hue_order = Some(order);
+    }
+
+    /// Pulls every unlocked foreground into `band` and rejects any later
+    /// move back out.
+    fn restrict_hue_band(&mut self, band: HueBand) {
+        self.fg_hue_band = Some(band);
+        for i in 0..self.fg_colors.len() {
+            if !self.locked[i] && self.fg_lch[i].chroma >= MAX_NEUTRAL_CHROMA {
+                self.fg_colors[i] = band.pull_into(self.fg_colors[i], self.space);
+                self.sync_slot(i);
+            }
+        }
+    }
+
+    /// Whether slot `i` is a near-gray or in the hue band, if any.
+    fn keeps_hue_band(&self, i: usize) -> bool {
+        match (self.fg_hue_band, self.fg_lch.get(i)) {
+            (Some(band), Some(lch)) => {
+                lch.chroma < MAX_NEUTRAL_CHROMA || band.contains(lch.hue.to_positive_degrees())
+            }
+            _ => true,
+        }
+    }
+
+    /// Moves fg color `i` to the nearest lightness (in LCH) at which it
+    /// meets the text contrast minimum on each of `bgs`, keeping its hue and
+    /// chroma. `None` if it already does; if no lightness works, the color
+    /// stays put and the nudge says so.
+    fn nudge_to_contrast(&mut self, i: usize, bgs: &[Color]) -> Option<Nudge> {
+        const STEP: f32 = 0.25;
+        let level = self.contrast_level;
+        let min_ratio = required_ratio(ContrastNeed::Text, level);
+        let passes = |c: Color| {
+            bgs.iter().all(|bg| {
+                ContrastRatio::for_pair(c, *bg, ContrastNeed::Text, level, self.space).value()
+                    >= min_ratio
+            })
+        };
+        let old = self.fg_colors[i];
+        if passes(old) {
+            return None;
+        }
+        let lch = to_lch(old, self.space);
+        // Try ever larger changes, lighter before darker at each size.
+        let found = (1..=(100. / STEP) as usize)
+            .flat_map(|k| [k as f32 * STEP, -(k as f32) * STEP])
+            .map(|dl| {
+                from_lch(
+                    Lch::new((lch.l + dl).clamp(0., 100.), lch.chroma, lch.hue),
+                    self.space,
+                )
+            })
+            .find(|c| passes(*c));
+        let new = found.unwrap_or(old);
+        self.fg_colors[i] = new;
+        self.sync_slot(i);
+        Some(Nudge {
+            fg_index: i,
+            delta_l: to_lch(new, self.space).l - lch.l,
+            delta_e: distance(old, new, self.space),
+            passes: found.is_some(),
+        })
+    }
+
+    /// Nudges every unlocked foreground that fails the text contrast
+    /// minimum on the main background to the nearest lightness that passes,
+    /// so annealing starts outside the penalty region.
+    fn feasible_start(&mut self) -> Vec<Nudge> {
+        let main = [self.bg_colors.get(BackgroundColors::FIXED)];
+        (0..self.fg_colors.len())
+            .filter_map(|i| match self.locked[i] {
+                true => None,
+                false => self.nudge_to_contrast(i, &main),
+            })
+            .collect()
+    }
+
+    /// Pins each foreground's LCH chroma at its current value.
+    fn pin_chroma(&mut self) {
+        self.fg_pinned_chroma = self.fg_lch.iter().map(|lch| Some(lch.chroma)).collect();
+    }
+
+    fn keeps_pinned_chroma(&self, i: usize) -> bool {
+        match self.fg_pinned_chroma.get(i).copied().flatten() {
+            Some(chroma) => (self.fg_lch[i].chroma - chroma).abs() <= CHROMA_TOLERANCE,
+            None => true,
+        }
+    }
+
+    fn keeps_hue_order(&self) -> bool {
+        let Some(order) = &self.fg_hue_order else {
+            return true;
+        };
+        let hue = |k: usize| self.fg_lch[k].hue.to_positive_degrees();
+        order.windows(2).all(|w| hue(w[0]) <= hue(w[1]))
+    }
+
+    /// Keeps the background with `role` gray, dropping its current chroma
+    /// down to [`MAX_NEUTRAL_CHROMA`] right away.
+    fn make_achromatic(&mut self, role: BgRole) {
+        let j = BackgroundColors::MODIFIABLE
+            .iter()
+            .position(|&r| r == role)
+            .expect("Only modifiable backgrounds can be made achromatic");
+        self.bg_achromatic[j] = true;
+        let i = self.fg_colors.len() + j;
+        *self.color_slot(i) = limit_chroma(self.bg_color_array[j], MAX_NEUTRAL_CHROMA, self.space);
+        self.sync_slot(i);
+    }
+
+    fn achromatic_roles(&self) -> impl Iterator<Item = BgRole> + '_ {
+        BackgroundColors::MODIFIABLE
+            .into_iter()
+            .zip(&self.bg_achromatic)
+            .filter_map(|(role, &a)| a.then_some(role))
+    }
+
+    fn lock_background(&mut self) {
+        for i in self.fg_colors.len()..self.n_slots() {
+            self.locked[i] = true;
+        }
+    }
+
+    fn sync_bg_slot(&mut self, mut i: usize) {
+        if i < self.fg_colors.len() {
+            return;
+        }
+        i -= self.fg_colors.len();
+        let mut a = self.bg_colors.updateable_array();
+        a[i] = self.bg_color_array[i];
+        self.bg_colors.update(a);
+    }
+
+    fn color_slot(&mut self, i: usize) -> &mut Color {
+        if i < self.fg_colors.len() {
+            &mut self.fg_colors[i]
+        } else {
+            &mut self.bg_color_array[i - self.fg_colors.len()]
+        }
+    }
+
+    /// Proposes one move per color slot at the given temperature,
+    /// accepting or rolling back each one. Returns the cost after the sweep.
+    fn sweep(
+        &mut self,
+        rng: &mut (impl RandRng + ?Sized),
+        bufs: &mut ScratchBuffers,
+        config: &OptimizeConfig,
+        temperature: f32,
+        mut old_cost: TotalCost,
+        stats: &mut AcceptanceStats,
+    ) -> TotalCost {
+        let decade = (Self::INITIAL_TEMPERATURE / temperature).log10().floor() as usize;
+        let wiggle = config.wiggle_at(temperature);
+        let mut order = std::mem::take(&mut bufs.order);
+        order.clear();
+        order.extend((0..self.n_slots()).filter(|&i| !self.locked[i]));
+        if config.shuffle_order {
+            order.shuffle(rng);
+        }
+        for &i in order.iter() {
+            let old_color;
+            {
+                let is_fg = i < self.fg_colors.len();
+                let pinned = is_fg && self.fg_pinned_chroma[i].is_some();
+                let space = self.space;
+                let slot = self.color_slot(i);
+                old_color = *slot;
+                if is_fg && !config.candidates.is_empty() {
+                    *slot = random_nearby_candidate(
+                        old_color,
+                        &config.candidates,
+                        Self::CANDIDATE_NEIGHBORS,
+                        space,
+                        rng,
+                    );
+                } else if pinned {
+                    let pre = random_nearby_color_fixed_chroma(old_color, wiggle, space, rng);
+                    *slot = clamp_to_gamut(pre);
+                    stats.record_clipping(clip_delta(pre, *slot));
+                } else {
+                    let clipping;
+                    (*slot, clipping) = config.perturbation.nearby_color(old_color, wiggle, rng);
+                    stats.record_clipping(clipping);
+                    if !is_fg && self.bg_achromatic[i - self.fg_colors.len()] {
+                        *self.color_slot(i) =
+                            limit_chroma(*self.color_slot(i), MAX_NEUTRAL_CHROMA, self.space);
+                    }
+                }
+                self.sync_slot(i);
+            }
+            if !self.keeps_hue_order() || !self.keeps_pinned_chroma(i) || !self.keeps_hue_band(i) {
+                stats.record(decade, false);
+                *self.color_slot(i) = old_color;
+                self.sync_slot(i);
+                continue;
+            }
+            // FIXME: Make this incremental for better performance!
+            let new_cost = self.total_cost(bufs);
+            let delta = new_cost.total(&self.weights) - old_cost.total(&self.weights);
+            let accept = match config.acceptance_rule {
+                AcceptanceRule::Metropolis => {
+                    let acceptance_probability = (-delta / temperature).exp();
+                    rng.gen_range(0. ..=1.) < acceptance_probability
+                }
+                AcceptanceRule::Threshold => delta < temperature,
+                AcceptanceRule::Greedy => delta <= 0.,
+            };
+            stats.record(decade, accept);
+            if accept {
+                old_cost = new_cost;
+            } else {
+                // Reset!
+                *self.color_slot(i) = old_color;
+                self.sync_slot(i);
+            }
+        }
+        bufs.order = order;
+        old_cost
+    }
+
+    /// Prints the current and best cost so far, and the share of moves
+    /// accepted, to stderr every `config.log_every` steps.
+    fn log_progress(
+        &self,
+        config: &OptimizeConfig,
+        n_iterations: u64,
+        temperature: f32,
+        cost: &TotalCost,
+        best_cost: f32,
+        acceptance: &AcceptanceStats,
+    ) {
+        if let Some(every) = config.log_every {
+            if n_iterations.is_multiple_of(every) {
+                eprintln!(
+                    "  step {n_iterations}: T={temperature:.4} cost={:.2} best={best_cost:.2} \
+                     accepted={:.1}%",
+                    cost.total(&self.weights),
+                    AcceptanceStats::rate(acceptance.accepted, acceptance.proposed)
+                );
+            }
+        }
+    }
+
+    fn optimize(&mut self, rng: &mut (impl RandRng + ?Sized), config: &OptimizeConfig) -> Report {
+        let mut bufs = ScratchBuffers::default();
+        let start_cost = self.total_cost(&mut bufs);
+        let start_state = self.clone();
+        let mut old_cost = start_cost.clone();
+
+        let mut temperature = Self::INITIAL_TEMPERATURE;
 
         let start_time = std::time::Instant::now();
         let mut n_iterations = 0;
+        let mut acceptance = AcceptanceStats::default();
+        let mut stop_reason = StopReason::Cutoff;
+        let mut best_total = start_cost.total(&self.weights);
 
         while temperature > Self::CUTOFF {
-            for i in 0..self.fg_colors.len() + BackgroundColors::MODIFIABLE_COUNT {
-                let old_color;
-                {
-                    let slot = self.color_slot(i);
-                    old_color = *slot;
-                    *slot = random_nearby_color(old_color, rng);
-                    self.sync_bg_slot(i);
-                }
-                // FIXME: Make this incremental for better performance!
-                let new_cost = self.total_cost(&mut bufs);
-                let delta = new_cost.total(&self.weights) - old_cost.total(&self.weights);
-                let acceptance_probability = (-delta / temperature).exp();
-                let accept = rng.gen_range(0. ..=1.) < acceptance_probability;
-                if accept {
-                    old_cost = new_cost;
-                } else {
-                    // Reset!
-                    *self.color_slot(i) = old_color;
-                    self.sync_bg_slot(i);
-                }
+            if config.hit_iteration_cap(n_iterations) {
+                stop_reason = StopReason::IterationCap;
+                break;
             }
+            old_cost = self.sweep(
+                rng,
+                &mut bufs,
+                config,
+                temperature,
+                old_cost,
+                &mut acceptance,
+            );
             n_iterations += 1;
+            best_total = best_total.min(old_cost.total(&self.weights));
+            self.log_progress(
+                config,
+                n_iterations,
+                temperature,
+                &old_cost,
+                best_total,
+                &acceptance,
+            );
             // Cooling
             temperature *= Self::COOLING_RATE;
         }
@@ -283,99 +2050,1961 @@ impl State {
             start_state,
             final_state: self.clone(),
             n_iterations,
+            stop_reason,
+            acceptance,
+            restart: 0,
+            n_restarts: 1,
+            fg_positions: None,
+            nudges: None,
+            start_nudges: None,
+            seed: None,
+            config: config.clone(),
             duration,
+            budget: None,
             weights: self.weights.clone(),
         }
     }
+
+    /// Anneals until `budget` has elapsed instead of until the cutoff
+    /// temperature. Whenever the schedule bottoms out, it is reheated from
+    /// the best state seen so far. Leaves `self` at the best state.
+    fn optimize_for_duration(
+        &mut self,
+        rng: &mut (impl RandRng + ?Sized),
+        config: &OptimizeConfig,
+        budget: Duration,
+    ) -> Report {
+        let mut bufs = ScratchBuffers::default();
+        let start_cost = self.total_cost(&mut bufs);
+        let start_state = self.clone();
+        let mut old_cost = start_cost.clone();
+        let mut best_cost = start_cost.clone();
+        let mut best_state = self.clone();
+
+        let mut temperature = Self::INITIAL_TEMPERATURE;
+
+        let start_time = std::time::Instant::now();
+        let mut n_iterations = 0;
+        let mut acceptance = AcceptanceStats::default();
+        let mut stop_reason = StopReason::Budget;
+
+        while start_time.elapsed() < budget {
+            if config.hit_iteration_cap(n_iterations) {
+                stop_reason = StopReason::IterationCap;
+                break;
+            }
+            old_cost = self.sweep(
+                rng,
+                &mut bufs,
+                config,
+                temperature,
+                old_cost,
+                &mut acceptance,
+            );
+            if old_cost.total(&self.weights) < best_cost.total(&self.weights) {
+                best_cost = old_cost.clone();
+                best_state = self.clone();
+            }
+            n_iterations += 1;
+            self.log_progress(
+                config,
+                n_iterations,
+                temperature,
+                &old_cost,
+                best_cost.total(&self.weights),
+                &acceptance,
+            );
+            temperature *= Self::COOLING_RATE;
+            if temperature <= Self::CUTOFF {
+                // Reheat
+                *self = best_state.clone();
+                old_cost = best_cost.clone();
+                temperature = Self::INITIAL_TEMPERATURE;
+            }
+        }
+
+        let duration = std::time::Instant::now() - start_time;
+        *self = best_state;
+
+        Report {
+            start_cost,
+            final_cost: best_cost,
+            start_state,
+            final_state: self.clone(),
+            n_iterations,
+            stop_reason,
+            acceptance,
+            restart: 0,
+            n_restarts: 1,
+            fg_positions: None,
+            nudges: None,
+            start_nudges: None,
+            seed: None,
+            config: config.clone(),
+            duration,
+            budget: Some(budget),
+            weights: self.weights.clone(),
+        }
+    }
+
+    /// Anneals following `config.phases`, for `budget` in total if given.
+    /// Alternating phases lock the foreground, then the background, on top
+    /// of any existing locks.
+    fn anneal(
+        &mut self,
+        rng: &mut (impl RandRng + ?Sized),
+        config: &OptimizeConfig,
+        budget: Option<Duration>,
+    ) -> Report {
+        let n_phases = match config.phases {
+            Phases::Joint => return self.anneal_phase(rng, config, budget),
+            Phases::Alternating => 2 * config.phase_rounds,
+        };
+        let budget = budget.map(|b| b / n_phases as u32);
+        let locked = self.locked.clone();
+        let mut report: Option<Report> = None;
+        for phase in 0..n_phases {
+            self.locked = locked.clone();
+            if phase % 2 == 0 {
+                self.lock_foreground();
+            } else {
+                self.lock_background();
+            }
+            let next = self.anneal_phase(rng, config, budget);
+            report = Some(match report {
+                None => next,
+                Some(report) => report.then(next),
+            });
+        }
+        self.locked = locked.clone();
+        let mut report = report.expect("At least one phase");
+        report.start_state.locked = locked.clone();
+        report.final_state.locked = locked;
+        report
+    }
+
+    fn anneal_phase(
+        &mut self,
+        rng: &mut (impl RandRng + ?Sized),
+        config: &OptimizeConfig,
+        budget: Option<Duration>,
+    ) -> Report {
+        match budget {
+            Some(budget) => self.optimize_for_duration(rng, config, budget),
+            None => self.optimize(rng, config),
+        }
+    }
+
+    /// The cost breakdown of the state as it stands, including whatever
+    /// `configure` applied to it.
+    fn evaluate(&self) -> TotalCost {
+        self.total_cost(&mut ScratchBuffers::default())
+    }
 }
 
-fn setup() -> Rng {
-    let args = args();
-    let rng;
+/// Computes the optimizer's cost breakdown for an existing palette without
+/// optimizing it. The palette is its own target, so `target_cost` is zero.
+pub fn evaluate(bg: BackgroundColors, fg: &[Color], weights: &Weights) -> TotalCost {
+    State::new(bg, fg.to_vec(), fg.to_vec(), weights.clone()).evaluate()
+}
+
+/// A palette in the shape that `--format json` prints one. Other fields,
+/// such as `foreground_positions`, are ignored.
+#[derive(Deserialize)]
+struct PaletteInput {
+    /// The active backgrounds, in [`BackgroundColors::into_array`] order.
+    background: Vec<String>,
+    foreground: Vec<String>,
+}
+
+/// Reads a palette as JSON from stdin and prints its cost breakdown and
+/// any violations, without optimizing it. Backgrounds that aren't active
+/// are taken from the dark mode palette. Fails if a hard constraint (a
+/// protected pair, or the text contrast minimum, or the Lc of a color's
+/// text use, for any fg ↔ bg pair) is violated, so that this can gate CI.
+fn lint(opts: &Options) -> Result<()> {
+    let stdin_error = |message: String| Error::Config {
+        path: "<stdin>".to_string(),
+        message,
+    };
+    let text = std::io::read_to_string(std::io::stdin()).map_err(|e| stdin_error(e.to_string()))?;
+    let input: PaletteInput =
+        serde_json::from_str(&text).map_err(|e| stdin_error(e.to_string()))?;
+    fn as_strs(v: &[String]) -> Vec<&str> {
+        v.iter().map(String::as_str).collect()
+    }
+    let active: [Color; BackgroundColors::COUNT] = colors_from_hex(&as_strs(&input.background))?
+        .try_into()
+        .map_err(|v: Vec<Color>| {
+            stdin_error(format!(
+                "expected {} background colors, got {}",
+                BackgroundColors::COUNT,
+                v.len()
+            ))
+        })?;
+    let fg = colors_from_hex(&as_strs(&input.foreground))?;
+    if fg.is_empty() {
+        return Err(Error::TooFewColors { needed: 1, got: 0 });
+    }
+    let mut bg = Mode::Dark.bg_colors();
+    bg.set_active(active);
+    let mut state = State::new(bg, fg.clone(), fg.clone(), weights(opts)?);
+    configure(&mut state, opts)?;
+    let cost = state.evaluate();
+
+    println!("Cost: {}", cost.total(&state.weights));
+    println!("{cost}");
+    let mut n_violations = 0;
+    // The same check as State::validate, so colors with a text use are
+    // judged by their APCA Lc.
+    for bg in active {
+        for (i, fg) in fg.iter().enumerate() {
+            let contrast = state.text_contrast(i, bg);
+            if !contrast.passes() {
+                n_violations += 1;
+                let hex = hex_colors(&[*fg, bg]);
+                println!("Contrast: {} on {}: {contrast}", hex[0], hex[1]);
+            }
+        }
+    }
+    for (pair, ratio) in state.violated_protected_pairs() {
+        n_violations += 1;
+        println!(
+            "Protected pair violated: {} on {}: {ratio} < {:.2}:1",
+            hex_colors(&[fg[pair.fg_index]])[0],
+            pair.bg_role.text(),
+            pair.min_ratio,
+        );
+    }
+    // Distance problems are worth a look, but not worth failing over.
+    for i in 0..fg.len() {
+        for j in (i + 1)..fg.len() {
+            let d = distance_lch(&state.fg_lch[i], &state.fg_lch[j]);
+            if d < DeltaE::PERCEPTIBLE {
+                let hex = hex_colors(&[fg[i], fg[j]]);
+                println!("Warning: {} and {} are only {d:.1} apart", hex[0], hex[1]);
+            }
+        }
+    }
+    let (d, i, j) = state.closest_fg_bg_pair();
+    if d < MIN_BG_DISTANCE {
+        let hex = hex_colors(&[fg[i], active[j]]);
+        println!(
+            "Warning: {} is only {d:.1} from background {} (minimum {MIN_BG_DISTANCE})",
+            hex[0], hex[1]
+        );
+    }
+    if n_violations > 0 {
+        return Err(Error::ConstraintsViolated(n_violations));
+    }
+    println!("OK");
+    Ok(())
+}
+
+/// Optimizes each mode's palette, then drops its least distinct color and
+/// re-optimizes until the palette meets every hard constraint, printing
+/// the largest palette that does, i.e. the fewest colors that had to go.
+fn minimize(opts: &Options) -> Result<()> {
+    let config = optimize_config(opts);
+    for mode in [Mode::Dark, Mode::Light] {
+        let (_, rng) = setup(opts);
+        let mut state = run_mode(mode, opts)?.final_state;
+        let mut dropped = vec![];
+        loop {
+            let n_broken = state.validate();
+            println!(
+                "{} mode, {} colors: {n_broken} constraint(s) broken",
+                mode.text(),
+                state.fg_colors.len()
+            );
+            if n_broken == 0 {
+                println!(
+                    "Viable with {} colors after dropping {:?}: {:?}\n",
+                    state.fg_colors.len(),
+                    dropped,
+                    hex_colors(&state.fg_colors)
+                );
+                break;
+            }
+            let Some(i) = state
+                .least_distinct_fg()
+                .filter(|_| state.fg_colors.len() > 2)
+            else {
+                println!("No viable palette with 2 or more colors\n");
+                break;
+            };
+            dropped.push(hex_colors(&[state.fg_colors[i]])[0].clone());
+            state.remove_fg(i);
+            state = optimize_with_restarts(&state, &rng, &config, opts).final_state;
+        }
+    }
+    Ok(())
+}
+
+/// Replaces each foreground color of `state` with the closest of `allowed`,
+/// e.g. a brand's approved colors. Several colors may snap to the same one.
+fn snap_to_palette(state: &State, allowed: &[Color]) -> State {
+    let mut snapped = state.clone();
+    for i in 0..snapped.fg_colors.len() {
+        if let Some(c) = get_closest_color(snapped.fg_colors[i], allowed, state.space) {
+            snapped.fg_colors[i] = c;
+            snapped.sync_slot(i);
+        }
+    }
+    snapped
+}
+
+/// Picks one more foreground color for `state`'s palette. Only the new
+/// color is annealed; everything else is locked, and the target term is
+/// dropped since the new color has no target to stay close to.
+fn add_color(state: &State, rng: &mut (impl RandRng + ?Sized)) -> Color {
+    let mut grown = state.clone();
+    grown.weights.target_weight = 0.;
+    grown.push_fg(random_color(rng));
+    let new = grown.fg_colors.len() - 1;
+    for (i, locked) in grown.locked.iter_mut().enumerate() {
+        *locked = i != new;
+    }
+    let report = grown.optimize(rng, &OptimizeConfig::default());
+    report.final_state.fg_colors[new]
+}
+
+/// Seeds the RNG from `opts`, or from entropy if no seed was given. The
+/// seed is returned so that it can be reported.
+fn setup(opts: &Options) -> (Seed, Rng) {
     std::env::set_var("RUST_BACKTRACE", "1");
-    if args.len() > 1 {
-        let arg_vec: Vec<_> = args.collect();
-        let seed_string = arg_vec[1].clone();
+    let seed = if let Some(seed) = opts.seed_bytes {
+        seed
+    } else if let Some(seed_string) = &opts.seed {
         let mut buf = [0u8; 32];
         let copy_len = 32.min(seed_string.len());
-        for i in 0..copy_len {
-            buf[i] = seed_string.as_bytes()[i];
-        }
-        rng = Rng::from_seed(buf);
+        buf[..copy_len].copy_from_slice(&seed_string.as_bytes()[..copy_len]);
+        buf
     } else {
-        rng = Rng::from_entropy();
+        rand::random()
+    };
+    (seed, Rng::from_seed(seed))
+}
+
+/// Runs `opts.restarts` independent optimizations from `state` and keeps
+/// the cheapest. Restart `i` uses stream `i` of the base RNG, so unless a
+/// time budget is set, results are deterministic for a given seed no matter
+/// how many threads run them.
+fn optimize_with_restarts(
+    state: &State,
+    rng: &Rng,
+    config: &OptimizeConfig,
+    opts: &Options,
+) -> Report {
+    let run = |restart: usize| {
+        let mut rng = rng.clone();
+        rng.set_stream(restart as u64);
+        let mut state = state.clone();
+        let mut report = state.anneal(&mut rng, config, opts.budget);
+        report.restart = restart;
+        report.n_restarts = opts.restarts;
+        report
+    };
+    if opts.restarts <= 1 {
+        return run(0);
+    }
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(opts.threads)
+        .build()
+        .expect("Failed to build restart thread pool");
+    let reports: Vec<Report> =
+        pool.install(|| (0..opts.restarts).into_par_iter().map(run).collect());
+    reports
+        .into_iter()
+        .min_by(|r1, r2| {
+            cmp_nan_last(
+                r1.final_cost.total(&r1.weights),
+                r2.final_cost.total(&r2.weights),
+            )
+        })
+        .expect("At least one restart")
+}
+
+/// Where [`mode_main`] prints the contrast and ΔE tables. They go to
+/// stderr when a machine-readable report is printed, so that stdout holds
+/// only the report.
+#[derive(Copy, Clone)]
+enum TableStream {
+    Stdout,
+    Stderr,
+}
+
+impl TableStream {
+    /// Stderr if any format other than the table and summary goes to
+    /// stdout.
+    fn for_options(opts: &Options) -> TableStream {
+        let to_stdout = |f: Format| !f.is_text() && opts.format_output(f).is_none();
+        if opts.formats.iter().any(|&f| to_stdout(f)) {
+            TableStream::Stderr
+        } else {
+            TableStream::Stdout
+        }
+    }
+
+    fn print(self, text: &str) {
+        match self {
+            TableStream::Stdout => print!("{text}"),
+            TableStream::Stderr => eprint!("{text}"),
+        }
+    }
+
+    fn print_table(self, t: &prettytable::Table) {
+        match self {
+            TableStream::Stdout => {
+                t.printstd();
+            }
+            // Like printstd, this drops write errors.
+            TableStream::Stderr => {
+                let _ = t.print(&mut std::io::stderr());
+            }
+        }
     }
-    return rng;
 }
 
-fn print_contrast_table(rows: Vec<Color>, cols: Vec<Color>, need: ContrastNeed) {
-    println!("");
-    let mut t = contrast_table(rows, cols, need);
-    t.sort_rows(&|cr1, cr2| {
-        let v1: Vec<_> = cr1.iter().map(|cr| cr.value()).collect();
-        let v2: Vec<_> = cr2.iter().map(|cr| cr.value()).collect();
-        root_mean_square(&v1)
-            .partial_cmp(&root_mean_square(&v2))
-            .expect("Failed float comparison!")
+fn print_contrast_table(
+    rows: Vec<Color>,
+    cols: Vec<Color>,
+    need: ContrastNeed,
+    level: ContrastLevel,
+    space: ColorSpace,
+    stream: TableStream,
+) {
+    stream.print("\n");
+    let mut t = contrast_table(rows, cols, need, level, space);
+    t.sort_rows_by_key(&|crs| {
+        let v: Vec<_> = crs.iter().map(|cr| cr.value()).collect();
+        root_mean_square(&v, Precision::default())
     });
-    t.table().printstd();
-    println!("");
+    stream.print_table(&t.table());
+    stream.print("\n");
+}
+
+fn print_delta_e_table(colors: Vec<Color>, v: Vision, space: ColorSpace, stream: TableStream) {
+    stream.print("\n");
+    stream.print_table(&delta_e_table(colors, v, space).table());
+    stream.print("\n");
+}
+
+/// Everything that decides how a run optimizes, as opposed to what it
+/// starts from.
+struct Tuning {
+    weights: Weights,
+    config: OptimizeConfig,
+}
+
+/// The outcome of [`compare`].
+struct Comparison {
+    mode: Mode,
+    /// Each run's final total, under its own weights.
+    totals: [f32; 2],
+    /// Each term's final unweighted value in both runs.
+    terms: Vec<(&'static str, [f32; 2])>,
+}
+
+impl Comparison {
+    /// "A" or "B" for the run with the lower value, or "tie".
+    fn winner(values: [f32; 2]) -> &'static str {
+        match cmp_nan_last(values[0], values[1]) {
+            std::cmp::Ordering::Less => "A",
+            std::cmp::Ordering::Greater => "B",
+            std::cmp::Ordering::Equal => "tie",
+        }
+    }
+}
+
+impl Display for Comparison {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} mode: A {:.2} vs B {:.2} (each under its own weights)",
+            self.mode.text(),
+            self.totals[0],
+            self.totals[1]
+        )?;
+        writeln!(
+            f,
+            "  {:<14}{:>10}{:>10}{:>10}  lower",
+            "term", "A", "B", "B - A"
+        )?;
+        for (name, values) in self.terms.iter() {
+            writeln!(
+                f,
+                "  {name:<14}{:>10.2}{:>10.2}{:>+10.2}  {}",
+                values[0],
+                values[1],
+                values[1] - values[0],
+                Self::winner(*values)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Optimizes `mode`'s built-in palette once with each tuning, starting
+/// both runs from the same RNG state so that only the tuning differs.
+/// Terms are compared unweighted, since the two runs may weigh them
+/// differently.
+fn compare(
+    mode: Mode,
+    bg_colors: BackgroundColors,
+    a: &Tuning,
+    b: &Tuning,
+    seed: Seed,
+    space: ColorSpace,
+) -> Comparison {
+    let run = |tuning: &Tuning| {
+        let mut state = State::new(
+            bg_colors,
+            mode.brand_colors(),
+            mode.brand_colors(),
+            tuning.weights.clone(),
+        );
+        state.set_color_space(space);
+        let report = state.optimize(&mut Rng::from_seed(seed), &tuning.config);
+        (report.final_cost.total(&tuning.weights), report.final_cost)
+    };
+    let (total_a, cost_a) = run(a);
+    let (total_b, cost_b) = run(b);
+    let terms = cost_a
+        .terms()
+        .into_iter()
+        .zip(cost_b.terms())
+        .map(|((name, x), (_, y))| (name, [x, y]))
+        .collect();
+    Comparison {
+        mode,
+        totals: [total_a, total_b],
+        terms,
+    }
+}
+
+/// The outcome of [`seed_stability`].
+struct StabilityReport {
+    /// The foreground colors every seed started from.
+    start: Vec<Color>,
+    /// Each seed's final total cost.
+    totals: Vec<f32>,
+    /// For each foreground, the mean Delta-E between where it ended up
+    /// under every pair of seeds.
+    per_color: Vec<f32>,
+    /// The mean of `per_color`.
+    overall: f32,
+}
+
+impl Display for StabilityReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (lowest, highest) = self
+            .totals
+            .iter()
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), &x| {
+                (lo.min(x), hi.max(x))
+            });
+        writeln!(
+            f,
+            "mean Delta-E {:.2} between the final palettes of {} seeds (final costs {lowest:.2} to {highest:.2})",
+            self.overall,
+            self.totals.len()
+        )?;
+        let hexes = hex_colors(&self.start);
+        for (i, (hex, d)) in hexes.iter().zip(&self.per_color).enumerate() {
+            // A color that lands perceptibly apart from seed to seed isn't
+            // pinned down by the weights.
+            let flag = if *d >= DeltaE::PERCEPTIBLE {
+                " (unsettled)"
+            } else {
+                ""
+            };
+            writeln!(f, "  {i:>3} {hex}: {d:>6.2}{flag}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Optimizes `state` under `weights` from `k` seeds, stream `i` of `rng`
+/// for seed `i` as with restarts, and measures how far apart the final
+/// foregrounds land. A low spread means the weights pin down one optimum;
+/// a high one means they leave the palette under-constrained, so which
+/// colors come out is mostly down to the seed.
+fn seed_stability(
+    state: &State,
+    weights: &Weights,
+    k: usize,
+    rng: &Rng,
+    config: &OptimizeConfig,
+    budget: Option<Duration>,
+) -> StabilityReport {
+    let reports: Vec<Report> = (0..k)
+        .into_par_iter()
+        .map(|i| {
+            let mut rng = rng.clone();
+            rng.set_stream(i as u64);
+            let mut state = state.clone();
+            state.weights = weights.clone();
+            state.anneal(&mut rng, config, budget)
+        })
+        .collect();
+    let per_color: Vec<f32> = (0..state.fg_colors.len())
+        .map(|c| {
+            let mut sum = 0.;
+            let mut n = 0;
+            for (a, ra) in reports.iter().enumerate() {
+                for rb in &reports[a + 1..] {
+                    sum += distance(
+                        ra.final_state.fg_colors[c],
+                        rb.final_state.fg_colors[c],
+                        state.space,
+                    );
+                    n += 1;
+                }
+            }
+            sum / n.max(1) as f32
+        })
+        .collect();
+    StabilityReport {
+        start: state.fg_colors.clone(),
+        totals: reports
+            .iter()
+            .map(|r| r.final_cost.total(weights))
+            .collect(),
+        overall: per_color.iter().sum::<f32>() / per_color.len().max(1) as f32,
+        per_color,
+    }
+}
+
+/// Prints how `colors` look under each color vision deficiency, flagging
+/// the ones where some pair falls below [`DeltaE::PERCEPTIBLE`].
+/// With `show_planes`, also prints which projection plane each color takes
+/// for the dichromat types, for debugging the simulation.
+fn simulate(colors: &[Color], luma: Luma, show_planes: bool, space: ColorSpace) -> Result<()> {
+    if colors.len() < 2 {
+        return Err(Error::TooFewColors {
+            needed: 2,
+            got: colors.len(),
+        });
+    }
+    println!("{:>14}: {:?}", "input", hex_colors(colors));
+    for v in Vision::all().skip(1) {
+        let seen: Vec<Color> = colors
+            .iter()
+            .map(|c| simulate_vision(*c, v, luma, space))
+            .collect();
+        let mut closest = (f32::INFINITY, 0, 0);
+        for i in 0..seen.len() {
+            for j in (i + 1)..seen.len() {
+                let d = distance(seen[i], seen[j], space);
+                if d < closest.0 {
+                    closest = (d, i, j);
+                }
+            }
+        }
+        let (d, i, j) = closest;
+        println!("{:>14}: {:?}", v.text(), hex_colors(&seen));
+        if show_planes {
+            let planes: Vec<String> = colors
+                .iter()
+                .filter_map(|c| brettel_plane(*c, v, space))
+                .map(|(plane, dot)| format!("{plane:?} ({dot:+.5})"))
+                .collect();
+            if !planes.is_empty() {
+                println!("{:>14}  planes {planes:?}", "");
+            }
+        }
+        let hex = hex_colors(&[colors[i], colors[j]]);
+        println!(
+            "{:>14}  min ΔE {d:.1} between {} and {}{}",
+            "",
+            hex[0],
+            hex[1],
+            if d < DeltaE::PERCEPTIBLE {
+                " (confusable)"
+            } else {
+                ""
+            }
+        );
+    }
+    Ok(())
+}
+
+/// Grays closer than this in L* are hard to tell apart in print.
+const MIN_GRAY_DISTANCE: f32 = 10.;
+
+/// How far the gamut clamp may pull a pinned chroma (see `--pin-chroma`)
+/// before the move is rejected.
+const CHROMA_TOLERANCE: f32 = 2.;
+
+/// Highest LCH chroma of a background that must stay achromatic; below
+/// this, a gray doesn't read as tinted.
+const MAX_NEUTRAL_CHROMA: f32 = 5.;
+
+/// Prints `colors` as grays of the same Rec.709 luminance, flagging every
+/// pair that lands within [`MIN_GRAY_DISTANCE`] of each other.
+fn print_grayscale(colors: &[Color], space: ColorSpace) {
+    let grays: Vec<Color> = colors.iter().map(|c| grayscale(*c, space)).collect();
+    let lightness: Vec<f32> = grays.iter().map(|c| to_lch(*c, space).l).collect();
+    println!("{:>14}: {:?}", "input", hex_colors(colors));
+    println!("{:>14}: {:?}", "grayscale", hex_colors(&grays));
+    println!("{:>14}  L* range {:.1}", "", max_minus_min(&lightness));
+    for i in 0..colors.len() {
+        for j in (i + 1)..colors.len() {
+            let d = (lightness[i] - lightness[j]).abs();
+            if d < MIN_GRAY_DISTANCE {
+                let hex = hex_colors(&[colors[i], colors[j]]);
+                println!(
+                    "{:>14}  {} and {} are only {d:.1} apart in L*",
+                    "", hex[0], hex[1]
+                );
+            }
+        }
+    }
 }
 
 fn main() {
-    mode_main(Mode::Dark);
-    mode_main(Mode::Light);
-}
-
-fn default_weights() -> Weights {
-    Weights {
-        contrast_weight: 2.,
-        distance_weight: 0.75,
-        range_weight: 0.25,
-        target_weight: 0.50,
-        protanopia_weight: 0.33,
-        deuteranopia_weight: 0.33,
-        tritanopia_weight: 0.33,
-        distance_bg_bg_weight: 0.1,
-        distance_bg_fg_weight: 0.2,
-        distance_fg_fg_weight: 0.7,
-        target_bg_weight: 0.1,
-        target_fg_weight: 0.9,
-        contrast_bg_bg_weight: 0.2,
-        contrast_bg_fg_weight: 0.8,
-    }
-    .initialize()
-}
-
-fn mode_main(mode: Mode) {
-    let bgs = mode.bg_colors().into_array().to_vec();
-    println!("{} mode background contrast", mode.text());
-    print_contrast_table(bgs.clone(), bgs.clone(), ContrastNeed::Background);
-
-    let fgs = mode.brand_colors();
-    println!("{} mode background ↔ foreground contrast", mode.text());
-    print_contrast_table(fgs.clone(), bgs.clone(), ContrastNeed::Text);
-
-    let mut rng = setup();
-
-    let mut state = State::new(mode.bg_colors(), mode.brand_colors(), default_weights());
-    let report = state.optimize(&mut rng);
-
-    let new_bg_colors = report.final_state.bg_colors.into_array().to_vec();
-    println!("Updated {} mode background contrast", mode.text());
-    print_contrast_table(
-        new_bg_colors.clone(),
-        new_bg_colors.clone(),
-        ContrastNeed::Background,
-    );
+    if let Err(e) = run() {
+        eprintln!("error: {e}");
+        std::process::exit(1);
+    }
+}
 
-    let new_fg_colors = report.final_state.fg_colors.clone();
-    print!("Updated {} mode bg ↔ fg contrast", mode.text());
-    print_contrast_table(
-        new_fg_colors.clone(),
-        new_bg_colors.clone(),
-        ContrastNeed::Text,
-    );
+fn run() -> Result<()> {
+    let mut opts = Options::parse()?;
+    if opts.seed.is_none() && opts.seed_bytes.is_none() {
+        // Pick the seed up front so both modes share it, and the one seed
+        // printed in the reports reproduces the whole run.
+        opts.seed_bytes = Some(rand::random());
+    }
+    match opts.command {
+        Command::Optimize => {}
+        Command::Simulate => {
+            return simulate(
+                &opts.colors,
+                opts.luma,
+                opts.shows(Verbosity::Verbose),
+                opts.color_space,
+            )
+        }
+        Command::Grayscale if !opts.colors.is_empty() => {
+            print_grayscale(&opts.colors, opts.color_space);
+            return Ok(());
+        }
+        Command::Grayscale => {
+            for mode in [Mode::Dark, Mode::Light] {
+                let report = run_mode(mode, &opts)?;
+                println!("{} mode", mode.text());
+                print_grayscale(&report.final_state.fg_colors, report.final_state.space);
+            }
+            return Ok(());
+        }
+        Command::Cost => return lint(&opts),
+        Command::Minimize => return minimize(&opts),
+        Command::Compare => {
+            let (seed, _) = setup(&opts);
+            let a = Tuning {
+                weights: weights(&opts)?,
+                config: optimize_config(&opts),
+            };
+            let b = Tuning {
+                weights: cvd_weights(
+                    load_weights(
+                        opts.compare_preset.unwrap_or(opts.preset),
+                        opts.compare_weights_file.as_deref(),
+                    )?,
+                    &opts,
+                ),
+                config: optimize_config(&opts),
+            };
+            println!("Seed: {}", seed_to_hex(&seed));
+            for mode in [Mode::Dark, Mode::Light] {
+                println!(
+                    "{}",
+                    compare(mode, bg_colors(mode, &opts), &a, &b, seed, opts.color_space)
+                );
+            }
+            return Ok(());
+        }
+        Command::Stability => {
+            let (seed, rng) = setup(&opts);
+            let weights = weights(&opts)?;
+            let config = optimize_config(&opts);
+            println!("Seed: {}", seed_to_hex(&seed));
+            for mode in [Mode::Dark, Mode::Light] {
+                let brand = mode.brand_colors();
+                let mut state = State::new(
+                    bg_colors(mode, &opts),
+                    brand.clone(),
+                    brand,
+                    weights.clone(),
+                );
+                configure(&mut state, &opts)?;
+                let stability = seed_stability(
+                    &state,
+                    &weights,
+                    opts.stability_seeds,
+                    &rng,
+                    &config,
+                    opts.budget,
+                );
+                println!("{} mode: {stability}", mode.text());
+            }
+            return Ok(());
+        }
+    }
+    if let Some(pattern) = &opts.batch {
+        return batch(&opts, pattern);
+    }
+    optimize_modes(&opts)?;
+    Ok(())
+}
+
+/// Optimizes both modes once and writes the report in each of
+/// `opts.formats` to its [`Options::format_output`].
+fn optimize_modes(opts: &Options) -> Result<(Report, Report)> {
+    // Open every output first, so a bad path fails before optimizing.
+    let mut outs = opts
+        .formats
+        .iter()
+        .map(|&format| Ok((format, Output::open(opts.format_output(format).as_deref())?)))
+        .collect::<Result<Vec<_>>>()?;
+    let mut discard = Output::discard();
+    let table_out = match outs.iter_mut().find(|(format, _)| *format == Format::Table) {
+        Some((_, out)) => out,
+        None => &mut discard,
+    };
+    let tables = TableStream::for_options(opts);
+    let dark = mode_main(Mode::Dark, opts, table_out, tables)?;
+    let light = mode_main(Mode::Light, opts, table_out, tables)?;
+    for (format, mut out) in outs {
+        write_format(format, opts, &dark, &light, &mut out)?;
+        out.finish()?;
+    }
+    Ok((dark, light))
+}
+
+/// Writes the reports of both modes in `format`, except for the table,
+/// which [`mode_main`] writes as it goes.
+fn write_format(
+    format: Format,
+    opts: &Options,
+    dark: &Report,
+    light: &Report,
+    out: &mut Output,
+) -> Result<()> {
+    match format {
+        Format::Table => {}
+        Format::Summary => {
+            for (mode, report) in [(Mode::Dark, dark), (Mode::Light, light)] {
+                out.write(&format!("{}\n", report.summary_line(mode)))?;
+            }
+        }
+        Format::Json => {
+            let doc = serde_json::json!({
+                Mode::Dark.text(): dark.to_json(),
+                Mode::Light.text(): light.to_json(),
+            });
+            out.write(&format!("{doc:#}\n"))?;
+        }
+        Format::ContrastJson => {
+            let matrix = |report: &Report| {
+                let state = &report.final_state;
+                contrast_table(
+                    state.fg_colors.clone(),
+                    state.bg_colors.into_array().to_vec(),
+                    ContrastNeed::Text,
+                    state.contrast_level,
+                    state.space,
+                )
+                .to_json()
+            };
+            let doc = serde_json::json!({
+                Mode::Dark.text(): matrix(dark),
+                Mode::Light.text(): matrix(light),
+            });
+            out.write(&format!("{doc:#}\n"))?;
+        }
+        Format::Ase => {
+            let names =
+                [Mode::Dark, Mode::Light].map(|mode| format!("category-colors {}", mode.text()));
+            out.write_bytes(&ase::swatches(&[
+                (&names[0], &dark.final_state.fg_colors),
+                (&names[1], &light.final_state.fg_colors),
+            ]))?;
+        }
+        Format::Base16 | Format::Base24 => {
+            let base24 = matches!(format, Format::Base24);
+            for (mode, report) in [(Mode::Dark, dark), (Mode::Light, light)] {
+                out.write("---\n")?;
+                out.write(&base16::scheme_yaml(
+                    &format!("category-colors {}", mode.text()),
+                    &report.final_state.bg_colors.into_array(),
+                    &report.final_state.fg_colors,
+                    &opts.base16_overrides,
+                    base24,
+                    report.final_state.space,
+                )?)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Runs [`optimize_modes`] on every palette file matching `pattern`, in
+/// parallel on `opts.threads` threads, writing each report to `NAME.optimized.EXT` and printing a
+/// table of the improvement per file.
+fn batch(opts: &Options, pattern: &str) -> Result<()> {
+    let invalid = |message: String| Error::Usage(format!("--batch {pattern}: {message}"));
+    let paths: Vec<std::path::PathBuf> = glob::glob(pattern)
+        .map_err(|e| invalid(e.to_string()))?
+        .collect::<std::result::Result<_, _>>()
+        .map_err(|e| invalid(e.to_string()))?;
+    // Reports from an earlier batch may match too, e.g. with `*.txt`.
+    let paths: Vec<_> = paths
+        .into_iter()
+        .filter(|path| {
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            !stem.ends_with(".optimized")
+        })
+        .collect();
+    if paths.is_empty() {
+        return Err(invalid("no matching files".to_string()));
+    }
+    let extension = opts.formats[0].extension();
+    let outputs: Vec<std::path::PathBuf> = paths
+        .iter()
+        .map(|path| {
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            let file_name = format!("{stem}.optimized.{extension}");
+            match &opts.batch_dir {
+                Some(dir) => std::path::Path::new(dir).join(file_name),
+                None => path.with_file_name(file_name),
+            }
+        })
+        .collect();
+    // With `--batch-dir`, palettes of the same name in different
+    // directories would overwrite each other's reports.
+    let mut seen = std::collections::HashMap::new();
+    for (path, output) in paths.iter().zip(&outputs) {
+        if let Some(other) = seen.insert(output, path) {
+            return Err(invalid(format!(
+                "{} and {} would both be written to {}",
+                other.display(),
+                path.display(),
+                output.display()
+            )));
+        }
+    }
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(opts.threads)
+        .build()
+        .expect("Failed to build batch thread pool");
+    let run = |(path, output): (&std::path::PathBuf, &std::path::PathBuf)| {
+        let mut opts = opts.clone();
+        opts.palette_file = Some(path.to_string_lossy().into_owned());
+        opts.output = Some(output.to_string_lossy().into_owned());
+        opts.verbosity = Verbosity::Quiet;
+        opts.progress = false;
+        let path = path.to_string_lossy().into_owned();
+        // Check the palette before creating its report file.
+        let result = read_color_file(&path).and_then(|_| optimize_modes(&opts));
+        (path, result)
+    };
+    let results: Vec<(String, Result<(Report, Report)>)> =
+        pool.install(|| paths.par_iter().zip(&outputs).map(run).collect());
+
+    let mut t = prettytable::Table::new();
+    t.set_format(*prettytable::format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+    t.set_titles(prettytable::row!["palette", "dark", "light"]);
+    let improvement = |report: &Report| {
+        format!(
+            "{:.1} → {:.1} ({:+.0}%)",
+            report.start_cost.total(&report.weights),
+            report.final_cost.total(&report.weights),
+            -report.improvement_pct()
+        )
+    };
+    let mut n_failed = 0;
+    for (path, result) in results.iter() {
+        match result {
+            Ok((dark, light)) => {
+                t.add_row(prettytable::row![
+                    path,
+                    improvement(dark),
+                    improvement(light)
+                ]);
+            }
+            Err(e) => {
+                n_failed += 1;
+                t.add_row(prettytable::row![path, H2 -> format!("error: {e}")]);
+            }
+        }
+    }
+    t.printstd();
+    if n_failed > 0 {
+        return Err(Error::BatchFailed(n_failed));
+    }
+    Ok(())
+}
+
+/// Where the final report goes: the `--output` file, or stdout.
+struct Output {
+    path: String,
+    sink: Box<dyn Write>,
+}
+
+impl Output {
+    fn open(path: Option<&str>) -> Result<Output> {
+        let Some(path) = path else {
+            return Ok(Output {
+                path: "stdout".to_string(),
+                sink: Box::new(std::io::stdout()),
+            });
+        };
+        let file = std::fs::File::create(path).map_err(|e| Error::Output {
+            path: path.to_string(),
+            message: e.to_string(),
+        })?;
+        Ok(Output {
+            path: path.to_string(),
+            sink: Box::new(std::io::BufWriter::new(file)),
+        })
+    }
+
+    /// Drops everything written to it.
+    fn discard() -> Output {
+        Output {
+            path: "nowhere".to_string(),
+            sink: Box::new(std::io::sink()),
+        }
+    }
+
+    fn write(&mut self, text: &str) -> Result<()> {
+        self.write_bytes(text.as_bytes())
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        self.sink.write_all(bytes).map_err(|e| self.error(e))
+    }
+
+    fn finish(mut self) -> Result<()> {
+        self.sink.flush().map_err(|e| self.error(e))
+    }
+
+    fn error(&self, e: std::io::Error) -> Error {
+        Error::Output {
+            path: self.path.clone(),
+            message: e.to_string(),
+        }
+    }
+}
+
+/// The weights of `opts.preset`, with any fields from `opts.weights_file`
+/// (a JSON object) overriding the preset's values, the CVD weights split as
+/// `opts.cvd_weights` says (or dropped with `opts.no_cvd`), and the target
+/// term dropped with `opts.no_target`.
+fn weights(opts: &Options) -> Result<Weights> {
+    let weights = load_weights(opts.preset, opts.weights_file.as_deref())?;
+    Ok(cvd_weights(weights, opts))
+}
+
+fn cvd_weights(weights: Weights, opts: &Options) -> Weights {
+    let mut weights = weights.with_cvd_weights(opts.cvd_weights);
+    if opts.no_cvd {
+        weights = weights.without_cvd();
+    }
+    if opts.no_target {
+        weights = weights.without_target();
+    }
+    weights
+}
+
+/// Reads hex colors separated by whitespace or commas from `path`.
+fn read_color_file(path: &str) -> Result<Vec<Color>> {
+    let config_error = |message: String| Error::Config {
+        path: path.to_string(),
+        message,
+    };
+    let text = std::fs::read_to_string(path).map_err(|e| config_error(e.to_string()))?;
+    let hexes: Vec<&str> = text
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|s| !s.is_empty())
+        .collect();
+    if hexes.is_empty() {
+        return Err(config_error("no colors".to_string()));
+    }
+    colors_from_hex(&hexes)
+}
+
+/// The weights of `preset`, with any fields from the JSON object in `file`
+/// overriding the preset's values.
+fn load_weights(preset: Preset, file: Option<&str>) -> Result<Weights> {
+    let weights = preset.weights();
+    let Some(path) = file else {
+        return Ok(weights);
+    };
+    let config_error = |message: String| Error::Config {
+        path: path.to_string(),
+        message,
+    };
+    let text = std::fs::read_to_string(path).map_err(|e| config_error(e.to_string()))?;
+    let overrides: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_str(&text).map_err(|e| config_error(format!("not a JSON object: {e}")))?;
+    let mut merged = serde_json::to_value(weights).expect("Weights serialize");
+    for (field, value) in overrides {
+        merged[field] = value;
+    }
+    let weights: Weights =
+        serde_json::from_value(merged).map_err(|e| config_error(e.to_string()))?;
+    weights
+        .check_sums()
+        .map_err(|e| config_error(e.to_string()))?;
+    Ok(weights.initialize())
+}
+
+fn optimize_config(opts: &Options) -> OptimizeConfig {
+    OptimizeConfig {
+        log_every: (opts.progress || opts.shows(Verbosity::Verbose)).then_some(100),
+        shuffle_order: opts.shuffle,
+        wiggle: opts.wiggle,
+        anneal_wiggle: opts.anneal_wiggle,
+        perturbation: opts.perturbation,
+        max_iterations: opts.max_iterations,
+        phases: opts.phases,
+        phase_rounds: opts.phase_rounds,
+        acceptance_rule: opts.acceptance_rule,
+        // Duplicates would crowd out other colors among a move's nearest
+        // candidates.
+        candidates: {
+            let mut seen = std::collections::HashSet::new();
+            opts.candidates
+                .iter()
+                .copied()
+                .filter(|c| seen.insert(color_key(*c)))
+                .collect()
+        },
+    }
+}
+
+/// `mode`'s built-in backgrounds, with the main one replaced by
+/// `--dark-bg` or `--light-bg` if given.
+fn bg_colors(mode: Mode, opts: &Options) -> BackgroundColors {
+    let mut bg = mode.bg_colors();
+    let main = match mode {
+        Mode::Dark => opts.dark_bg,
+        Mode::Light => opts.light_bg,
+    };
+    if let Some(c) = main {
+        bg.set(BgRole::Main, c);
+    }
+    bg
+}
+
+/// Optimizes the built-in palette for `mode` as configured by `opts`.
+fn run_mode(mode: Mode, opts: &Options) -> Result<Report> {
+    let (seed, mut rng) = setup(opts);
+
+    let config = optimize_config(opts);
+    let targets = match &opts.targets_file {
+        Some(path) => Some(read_color_file(path)?),
+        None => None,
+    };
+    let mut state = match opts.generate {
+        // Random colors with nothing to stay close to (unless targets are
+        // given), so only distance, contrast and the a11y terms shape the
+        // result.
+        Some(n) => {
+            let fg = (0..n).map(|_| random_color(&mut rng)).collect();
+            let mut weights = weights(opts)?;
+            let targets = targets.unwrap_or_default();
+            if targets.is_empty() {
+                weights.target_weight = 0.;
+            }
+            State::new(bg_colors(mode, opts), fg, targets, weights)
+        }
+        None => {
+            let fg = match &opts.palette_file {
+                Some(path) => read_color_file(path)?,
+                None => mode.brand_colors(),
+            };
+            let targets = targets.unwrap_or_else(|| fg.clone());
+            State::new(bg_colors(mode, opts), fg, targets, weights(opts)?)
+        }
+    };
+    configure(&mut state, opts)?;
+    if !config.candidates.is_empty() {
+        // Moves only go from candidate to candidate, so start on one.
+        state = snap_to_palette(&state, &config.candidates);
+    }
+    if opts.background_only {
+        state.lock_foreground();
+    }
+    if let Some(band) = opts.hue_band {
+        state.restrict_hue_band(band);
+    }
+    let start_nudges = opts.feasible_start.then(|| state.feasible_start());
+    if opts.keep_hue_order {
+        state.keep_hue_order();
+    }
+    if opts.pin_chroma {
+        state.pin_chroma();
+    }
+    let mut report = optimize_with_restarts(&state, &rng, &config, opts);
+    report.seed = Some(seed);
+    report.start_nudges = start_nudges;
+    if opts.contrast_floor {
+        report.apply_contrast_floor();
+    }
+    match opts.sort_output {
+        SortOutput::None => {}
+        SortOutput::Hue => report.sort_final_by_hue(),
+    }
+    Ok(report)
+}
 
-    println!("{report}");
+/// Applies the per-color constraints and weights from `opts` to `state`.
+fn configure(state: &mut State, opts: &Options) -> Result<()> {
+    let out_of_range = |what: &str, i: usize| {
+        Error::Usage(format!(
+            "{what} index {i} out of range; there are {} foreground colors",
+            state.fg_colors.len()
+        ))
+    };
+    for pair in opts.protected_pairs.iter() {
+        if pair.fg_index >= state.fg_colors.len() {
+            return Err(out_of_range("Protected pair", pair.fg_index));
+        }
+    }
+    for &(i, _) in opts.importance.iter() {
+        if i >= state.fg_colors.len() {
+            return Err(out_of_range("Importance", i));
+        }
+    }
+    for &(i, _) in opts.text_uses.iter() {
+        if i >= state.fg_colors.len() {
+            return Err(out_of_range("Text use", i));
+        }
+    }
+    // Catch a bad --base16-map before optimizing rather than after.
+    if opts
+        .formats
+        .iter()
+        .any(|f| matches!(f, Format::Base16 | Format::Base24))
+    {
+        base16::check_sources(
+            state.bg_colors.into_array().len(),
+            state.fg_colors.len(),
+            &opts.base16_overrides,
+        )?;
+    }
+    state.set_color_space(opts.color_space);
+    state.protected_pairs = opts.protected_pairs.clone();
+    state.contrast_level = opts.contrast_level;
+    state.contrast_sigmoid = opts.contrast_sigmoid;
+    state.cvd_severity = opts.cvd_severity;
+    state.luma = opts.luma;
+    state.precision = opts.precision;
+    state.distance_objective = opts.distance_objective;
+    for &(i, weight) in opts.importance.iter() {
+        state.fg_importance[i] = weight;
+    }
+    for &(i, text_use) in opts.text_uses.iter() {
+        state.fg_text_use[i] = Some(text_use);
+    }
+    for &role in opts.achromatic.iter() {
+        state.make_achromatic(role);
+    }
+    state.refresh_contrast_cache();
+    Ok(())
+}
+
+fn mode_main(mode: Mode, opts: &Options, out: &mut Output, tables: TableStream) -> Result<Report> {
+    let (level, space) = (opts.contrast_level, opts.color_space);
+    let show_tables = opts.shows(Verbosity::Normal);
+    // Escape codes would only clutter piped output.
+    let show_preview = opts.color_preview && std::io::stdout().is_terminal();
+    let report = run_mode(mode, opts)?;
+
+    if show_tables {
+        // The starting palette isn't always the brand colors (see
+        // --palette and --generate), so take it from the report.
+        let bgs = report.start_state.bg_colors.into_array().to_vec();
+        let fgs = report.start_state.fg_colors.clone();
+        if show_preview {
+            tables.print(&format!("{} mode palette\n\n", mode.text()));
+            tables.print(&format!("{}\n", preview::palette_preview(&bgs, &fgs)));
+        }
+        tables.print(&format!("{} mode background contrast\n", mode.text()));
+        print_contrast_table(
+            bgs.clone(),
+            bgs.clone(),
+            ContrastNeed::Background,
+            level,
+            space,
+            tables,
+        );
+
+        tables.print(&format!(
+            "{} mode background ↔ foreground contrast\n",
+            mode.text()
+        ));
+        print_contrast_table(fgs, bgs, ContrastNeed::Text, level, space, tables);
+
+        let new_bg_colors = report.final_state.bg_colors.into_array().to_vec();
+        tables.print(&format!(
+            "Updated {} mode background contrast\n",
+            mode.text()
+        ));
+        print_contrast_table(
+            new_bg_colors.clone(),
+            new_bg_colors.clone(),
+            ContrastNeed::Background,
+            level,
+            space,
+            tables,
+        );
+
+        let new_fg_colors = report.final_state.fg_colors.clone();
+        if show_preview {
+            tables.print(&format!("Updated {} mode palette\n\n", mode.text()));
+            tables.print(&format!(
+                "{}\n",
+                preview::palette_preview(&new_bg_colors, &new_fg_colors)
+            ));
+        }
+        tables.print(&format!("Updated {} mode bg ↔ fg contrast", mode.text()));
+        print_contrast_table(
+            new_fg_colors.clone(),
+            new_bg_colors.clone(),
+            ContrastNeed::Text,
+            level,
+            space,
+            tables,
+        );
+
+        tables.print(&format!(
+            "Updated {} mode fg ↔ fg ΔE (CIEDE2000)\n",
+            mode.text()
+        ));
+        print_delta_e_table(new_fg_colors.clone(), Vision::Default, space, tables);
+
+        if let Some(v) = opts.vision_table {
+            tables.print(&format!(
+                "Updated {} mode fg ↔ fg ΔE under {}\n",
+                mode.text(),
+                v.text()
+            ));
+            print_delta_e_table(new_fg_colors, v, space, tables);
+        }
+    }
+
+    if opts.formats.contains(&Format::Table) {
+        out.write(&format!("{report}\n"))?;
+        if !opts.snap_to.is_empty() {
+            let snapped = snap_to_palette(&report.final_state, &opts.snap_to);
+            let cost = snapped.total_cost(&mut ScratchBuffers::default());
+            out.write(&format!(
+                "Snapped to {} allowed colors: cost {} → {}\n{}\n        ↓\n{cost}\n",
+                opts.snap_to.len(),
+                report.final_cost.total(&report.weights),
+                cost.total(&report.weights),
+                report.final_cost,
+            ))?;
+            out.write(&format!(
+                "Snapped foreground colors:\n  {:?}\n\n",
+                brand_named_colors(&snapped.fg_colors, report.final_state.space)
+            ))?;
+        }
+        if opts.add_color {
+            let extra = add_color(&report.final_state, &mut setup(opts).1);
+            out.write(&format!(
+                "Suggested additional color: {}\n\n",
+                brand_named_colors(&[extra], report.final_state.space)[0]
+            ))?;
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::{prop_assert, proptest, ProptestConfig};
+
+    use super::*;
+
+    /// `n` random foregrounds on the dark backgrounds, targeting themselves.
+    fn random_state(seed: u64, n: usize, weights: Weights) -> State {
+        let mut rng = Rng::seed_from_u64(seed);
+        let fg: Vec<Color> = (0..n).map(|_| random_color(&mut rng)).collect();
+        State::new(Mode::Dark.bg_colors(), fg.clone(), fg, weights)
+    }
+
+    fn in_gamut(c: Color) -> bool {
+        [c.red, c.green, c.blue]
+            .iter()
+            .all(|x| (0. ..=1.).contains(x))
+    }
+
+    #[test]
+    fn shuffled_order_runs_the_full_schedule_to_a_valid_palette() {
+        let config = OptimizeConfig {
+            shuffle_order: true,
+            ..OptimizeConfig::default()
+        };
+        for seed in 0..2 {
+            let mut state = random_state(seed, 3, Preset::Balanced.weights().without_cvd());
+            let report = state.optimize(&mut Rng::seed_from_u64(seed), &config);
+            assert_eq!(report.stop_reason, StopReason::Cutoff);
+            let final_state = &report.final_state;
+            assert_eq!(final_state.fg_colors.len(), 3);
+            assert!(final_state.fg_colors.iter().all(|c| in_gamut(*c)));
+            assert!(final_state
+                .bg_colors
+                .into_array()
+                .iter()
+                .all(|c| in_gamut(*c)));
+            assert!(report.final_cost.total(&report.weights).is_finite());
+        }
+    }
+
+    #[test]
+    fn lock_foreground_moves_only_the_backgrounds() {
+        let mut state = random_state(3, 4, Preset::Balanced.weights());
+        state.lock_foreground();
+        let (fg, bg) = (state.fg_colors.clone(), state.bg_colors.into_array());
+        let config = OptimizeConfig {
+            max_iterations: Some(50),
+            ..OptimizeConfig::default()
+        };
+        let report = state.optimize(&mut Rng::seed_from_u64(3), &config);
+        assert_eq!(report.final_state.fg_colors, fg);
+        assert_ne!(report.final_state.bg_colors.into_array(), bg);
+    }
+
+    #[test]
+    fn color_space_is_per_state() {
+        let srgb = random_state(5, 4, Preset::Balanced.weights());
+        let mut p3 = srgb.clone();
+        p3.set_color_space(ColorSpace::DisplayP3);
+        // Scoring checks (in debug builds) that the caches match the space.
+        assert_ne!(p3.evaluate(), srgb.evaluate());
+        assert_eq!(p3.fg_colors, srgb.fg_colors);
+    }
+
+    #[test]
+    fn tables_leave_stdout_to_a_machine_readable_report() {
+        let stream = |formats: Vec<Format>, output: Option<&str>| {
+            let opts = Options {
+                formats,
+                output: output.map(String::from),
+                ..Options::default()
+            };
+            TableStream::for_options(&opts)
+        };
+        let on_stderr = |s| matches!(s, TableStream::Stderr);
+        assert!(!on_stderr(stream(vec![Format::Table], None)));
+        assert!(!on_stderr(stream(vec![Format::Summary], None)));
+        assert!(on_stderr(stream(vec![Format::Json], None)));
+        assert!(!on_stderr(stream(vec![Format::Json], Some("out.json"))));
+        let table_and_json = vec![Format::Table, Format::Json];
+        assert!(!on_stderr(stream(table_and_json, Some("out"))));
+    }
+
+    #[test]
+    fn optimize_never_moves_the_main_background() {
+        let config = OptimizeConfig {
+            max_iterations: Some(50),
+            ..OptimizeConfig::default()
+        };
+        for seed in 0..3 {
+            let mut state = random_state(seed, 4, Preset::Balanced.weights());
+            let main = state.bg_colors.get(BackgroundColors::FIXED);
+            let report = state.optimize(&mut Rng::seed_from_u64(seed), &config);
+            let bg = report.final_state.bg_colors;
+            assert_eq!(bg.get(BackgroundColors::FIXED), main);
+            assert_ne!(bg.into_array(), report.start_state.bg_colors.into_array());
+        }
+    }
+
+    #[test]
+    fn zero_cvd_weights_skip_the_cvd_terms() {
+        let with_cvd = random_state(1, 5, Preset::Balanced.weights());
+        let without_cvd = State {
+            weights: with_cvd.weights.clone().without_cvd(),
+            ..with_cvd.clone()
+        };
+        let mut bufs = ScratchBuffers::default();
+        let full = with_cvd.total_cost(&mut bufs);
+        assert!(full.protanopia_cost > 0. && full.tritanopia_cost > 0.);
+        // Only the CVD terms depend on the CVD weights.
+        let expected = TotalCost {
+            protanopia_cost: 0.,
+            deuteranopia_cost: 0.,
+            tritanopia_cost: 0.,
+            ..full
+        };
+        assert_eq!(without_cvd.total_cost(&mut bufs), expected);
+    }
+
+    #[test]
+    fn batch_dir_rejects_palettes_with_the_same_name() {
+        let root = std::env::temp_dir().join(format!("batch-collide-{}", std::process::id()));
+        for dir in ["a", "b"] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+            std::fs::write(root.join(dir).join("p.txt"), "#ffc247\n#00a1c7\n").unwrap();
+        }
+        let opts = Options {
+            batch_dir: Some(root.join("out").to_string_lossy().into_owned()),
+            ..Options::default()
+        };
+        let result = batch(&opts, &root.join("*/p.txt").to_string_lossy());
+        std::fs::remove_dir_all(&root).unwrap();
+        match result {
+            Err(Error::Usage(message)) => assert!(message.contains("both"), "{message}"),
+            other => panic!("expected a usage error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn disabled_target_term_skips_the_target_distances() {
+        // Start away from the targets, so measured distances would show.
+        let with_target = State::new(
+            Mode::Dark.bg_colors(),
+            Mode::Light.brand_colors(),
+            Mode::Dark.brand_colors(),
+            Preset::Balanced.weights(),
+        );
+        assert!(with_target.fg_target_distances.iter().any(|&d| d > 0.));
+        let without_target = State::new(
+            Mode::Dark.bg_colors(),
+            Mode::Light.brand_colors(),
+            Mode::Dark.brand_colors(),
+            Preset::Balanced.weights().without_target(),
+        );
+        let cached = [
+            &without_target.bg_target_distances,
+            &without_target.fg_target_distances,
+        ];
+        assert!(cached.iter().all(|ds| ds.iter().all(|&d| d == 0.)));
+
+        let mut bufs = ScratchBuffers::default();
+        let expected = TotalCost {
+            target_cost: 0.,
+            ..with_target.total_cost(&mut bufs)
+        };
+        assert_eq!(without_target.total_cost(&mut bufs), expected);
+    }
+
+    #[test]
+    fn text_use_colors_are_judged_by_their_lc() {
+        // About 3.2:1 and Lc 25 on the dark canvas: too little for WCAG
+        // text, enough for large bold text under APCA.
+        let fg = colors_from_hex(&["#707070"]).unwrap();
+        let mut state = State::new(
+            Mode::Dark.bg_colors(),
+            fg.clone(),
+            fg,
+            Preset::Balanced.weights(),
+        );
+        let main = state.bg_colors.get(BgRole::Main);
+        assert!(!state.text_contrast(0, main).passes());
+        let broken_by_wcag = state.validate();
+        state.fg_text_use[0] = Some("100/700".parse().unwrap());
+        assert!(state.text_contrast(0, main).passes());
+        assert!(state.validate() < broken_by_wcag);
+    }
+
+    #[test]
+    fn maximin_with_heavy_importance_stays_in_range() {
+        // Two near-identical foregrounds, so the fg ↔ fg pair, weighted 4 × 4,
+        // falls almost the whole ideal distance short.
+        let fg = colors_from_hex(&["#ffc247", "#ffc248", "#00a1c7"]).unwrap();
+        let mut state = State::new(
+            Mode::Dark.bg_colors(),
+            fg.clone(),
+            fg,
+            Preset::Balanced.weights(),
+        );
+        let opts = Options {
+            distance_objective: DistanceObjective::Maximin,
+            importance: vec![(0, 4.), (1, 4.)],
+            ..Options::default()
+        };
+        configure(&mut state, &opts).unwrap();
+        let score = state.distance_cost(&mut ScratchBuffers::default(), Vision::Default);
+        assert!(score.value() > 50., "{}", score.value());
+        assert!(state.evaluate().total(&state.weights).is_finite());
+    }
+
+    #[test]
+    fn evaluate_scores_the_palette_against_itself() {
+        let weights = Preset::Balanced.weights();
+        let mut state = random_state(7, 4, weights.clone());
+        let cost = evaluate(state.bg_colors, &state.fg_colors, &weights);
+        assert_eq!(cost.target_cost, 0.);
+        configure(&mut state, &Options::default()).unwrap();
+        assert_eq!(cost, state.evaluate());
+    }
+
+    /// Evaluates every cost term on degenerate palettes, where the sub-scores
+    /// sit at the ends of their ranges. A change that lets a term leave
+    /// [`ScaledCost`]'s 0..=100 range trips its assertion here, rather than
+    /// midway through a long run.
+    #[test]
+    fn extreme_palettes_keep_costs_in_range() {
+        const BLACK: &str = "#000000";
+        const WHITE: &str = "#ffffff";
+        const BLACK_AND_WHITE: [&str; BackgroundColors::COUNT] =
+            [BLACK, WHITE, BLACK, WHITE, BLACK, WHITE];
+        let palettes: [(&str, [&str; BackgroundColors::COUNT], Vec<&str>); 4] = [
+            (
+                "all black",
+                [BLACK; BackgroundColors::COUNT],
+                vec![BLACK; 8],
+            ),
+            (
+                "all white",
+                [WHITE; BackgroundColors::COUNT],
+                vec![WHITE; 8],
+            ),
+            (
+                "black and white",
+                BLACK_AND_WHITE,
+                vec![WHITE, BLACK, WHITE, BLACK, WHITE, BLACK],
+            ),
+            (
+                "primaries",
+                BLACK_AND_WHITE,
+                vec![
+                    "#ff0000", "#00ff00", "#0000ff", "#00ffff", "#ff00ff", "#ffff00",
+                ],
+            ),
+        ];
+        let invert = |c: Color| Color::new(1. - c.red, 1. - c.green, 1. - c.blue);
+        let mut bufs = ScratchBuffers::default();
+        for (name, bg, fg) in palettes {
+            let mut bg_colors = Mode::Dark.bg_colors();
+            bg_colors.set_active(bg.map(rgb));
+            let fg: Vec<Color> = fg.into_iter().map(rgb).collect();
+            for preset in [
+                Preset::Balanced,
+                Preset::AccessibilityFirst,
+                Preset::BrandFaithful,
+            ] {
+                for importance in [1., 0., 100.] {
+                    let mut state = State::new(bg_colors, fg.clone(), fg.clone(), preset.weights());
+                    state.fg_importance.fill(importance);
+                    // On target, then as far from the targets as each color gets.
+                    let on_target = state.total_cost(&mut bufs);
+                    for i in 0..state.n_slots() {
+                        let slot = state.color_slot(i);
+                        *slot = invert(*slot);
+                        state.sync_slot(i);
+                    }
+                    let inverted = state.total_cost(&mut bufs);
+                    for cost in [on_target, inverted] {
+                        assert!(cost.total(&state.weights).is_finite(), "{name}");
+                    }
+                }
+            }
+        }
+    }
+
+    /// Checks that with a reference palette distinct from the starting colors,
+    /// the target term measures distance to the reference, not to the start.
+    #[test]
+    fn target_term_measures_distance_to_the_reference() {
+        let start: Vec<Color> = ["#ee1111", "#00ff00", "#1111ee"].map(rgb).to_vec();
+        let reference: Vec<Color> = ["#ff0000", "#0000ff"].map(rgb).to_vec();
+        let weights = Preset::Balanced.weights();
+        let state = State::new(
+            Mode::Dark.bg_colors(),
+            start.clone(),
+            reference.clone(),
+            weights.clone(),
+        );
+        let distances: Vec<f32> = start
+            .iter()
+            .map(|c| {
+                distance(
+                    *c,
+                    get_closest_color(*c, &reference, state.space).unwrap(),
+                    state.space,
+                )
+            })
+            .collect();
+        // The backgrounds are their own targets, so only the fg part remains.
+        let expected = root_mean_square(&distances, state.precision) * weights.target_fg_weight;
+        let actual = state.target_cost().value();
+        assert!(
+            (actual - expected).abs() < 1e-3,
+            "target cost {actual} is not the distance to the reference ({expected})"
+        );
+        let anchored_to_start = State::new(Mode::Dark.bg_colors(), start.clone(), start, weights);
+        assert_eq!(anchored_to_start.target_cost().value(), 0.);
+    }
+
+    proptest! {
+        // Each case runs a full schedule, which takes seconds in a debug build.
+        #![proptest_config(ProptestConfig::with_cases(8))]
+
+        /// Checks that a full annealing schedule never leaves a random palette
+        /// much worse than it started. Annealing accepts worse moves along the
+        /// way, but by the cutoff it only goes downhill, so a final cost above
+        /// the start (plus some noise) points at a bug in acceptance or cooling.
+        /// Small palettes without the CVD simulation keep each run short, as
+        /// capping the iterations would stop while the temperature still
+        /// allows uphill moves.
+        #[test]
+        fn annealing_never_leaves_a_palette_worse(seed: u64, n in 2..=4usize) {
+            const TOLERANCE: f32 = 1.05;
+            let mut rng = Rng::seed_from_u64(seed);
+            let fg: Vec<Color> = (0..n).map(|_| random_color(&mut rng)).collect();
+            let mut state = State::new(
+                Mode::Dark.bg_colors(),
+                fg.clone(),
+                fg,
+                Preset::Balanced.weights().without_cvd(),
+            );
+            let report = state.optimize(&mut rng, &OptimizeConfig::default());
+            let start = report.start_cost.total(&report.weights);
+            let end = report.final_cost.total(&report.weights);
+            prop_assert!(
+                end <= start * TOLERANCE,
+                "{n} colors got worse: {start} → {end}"
+            );
+        }
+    }
+
+    /// Checks that printing colors as hex and parsing them back moves each
+    /// channel by at most half an 8-bit step, and keeps the color's key.
+    #[test]
+    fn hex_round_trip_keeps_colors_within_half_a_step() {
+        const EPS: f32 = 0.5 / 255. + 1e-6;
+        let mut rng = Rng::seed_from_u64(0);
+        let mut colors: Vec<Color> = (0..1000).map(|_| random_color(&mut rng)).collect();
+        colors.extend(Mode::Dark.brand_colors());
+        colors.extend(Mode::Light.brand_colors());
+        let hexes = hex_colors(&colors);
+        let hexes: Vec<&str> = hexes.iter().map(String::as_str).collect();
+        let parsed = colors_from_hex(&hexes).expect("Printed hex colors parse");
+        for (c, back) in colors.iter().zip(parsed) {
+            assert!(
+                colors_approx_eq(*c, back, EPS),
+                "{c:?} came back as {back:?}"
+            );
+            assert_eq!(color_key(*c), color_key(back), "{c:?} changed its key");
+        }
+    }
+
+    /// Checks that palettes of zero, one and two colors optimize to a finite
+    /// cost, with nothing to separate scoring as the best fg ↔ fg distance.
+    #[test]
+    fn tiny_palettes_optimize_to_finite_costs() {
+        let config = OptimizeConfig {
+            max_iterations: Some(50),
+            ..OptimizeConfig::default()
+        };
+        for n in 0..=2 {
+            let mut rng = Rng::seed_from_u64(n as u64);
+            let fg: Vec<Color> = (0..n).map(|_| random_color(&mut rng)).collect();
+            let mut state = State::new(
+                Mode::Dark.bg_colors(),
+                fg.clone(),
+                fg,
+                Preset::Balanced.weights(),
+            );
+            let report = state.optimize(&mut rng, &config);
+            for cost in [&report.start_cost, &report.final_cost] {
+                let total = cost.total(&report.weights);
+                assert!(total.is_finite(), "{n}-color palette cost {total}");
+            }
+            if n < 2 {
+                let alone = State {
+                    weights: Weights {
+                        distance_bg_bg_weight: 0.,
+                        distance_bg_fg_weight: 0.,
+                        distance_fg_fg_weight: 1.,
+                        ..report.weights.clone()
+                    },
+                    ..report.final_state
+                };
+                let score = alone.distance_cost(&mut ScratchBuffers::default(), Vision::Default);
+                assert_eq!(score.value(), 0., "{n}-color palette has a distance score");
+            }
+        }
+    }
+
+    /// Compares costs summed in f32 and f64 on a large palette: the cost of
+    /// one state must agree closely, and each state keeps its precision
+    /// through annealing.
+    #[test]
+    fn f32_and_f64_sums_agree() {
+        const N_COLORS: usize = 50;
+        let config = OptimizeConfig {
+            max_iterations: Some(2),
+            ..OptimizeConfig::default()
+        };
+        let mut rng = Rng::seed_from_u64(0);
+        let fg: Vec<Color> = (0..N_COLORS).map(|_| random_color(&mut rng)).collect();
+        let state = State::new(
+            Mode::Dark.bg_colors(),
+            fg.clone(),
+            fg,
+            Preset::Balanced.weights(),
+        );
+        let run = |precision| {
+            let mut state = State {
+                precision,
+                ..state.clone()
+            };
+            let start = state.total_cost(&mut ScratchBuffers::default());
+            let report = state.optimize(&mut Rng::seed_from_u64(1), &config);
+            assert_eq!(report.final_state.precision, precision);
+            assert!(report.final_cost.total(&report.weights).is_finite());
+            start.total(&report.weights)
+        };
+        let (start_32, start_64) = (run(Precision::F32), run(Precision::F64));
+        assert!(
+            (start_32 - start_64).abs() <= 1e-4 * start_64,
+            "f32 and f64 sums disagree on the same palette: {start_32} vs {start_64}"
+        );
+    }
+
+    /// Starts the dark palette with the git added and deleted backgrounds
+    /// collapsed onto one color, and checks that the bg ↔ bg distance term
+    /// notices and that annealing pulls them perceptibly apart again.
+    #[test]
+    fn collapsed_git_backgrounds_are_pulled_apart() {
+        let mut bg_colors = Mode::Dark.bg_colors();
+        let collapsed = bg_colors.get(BgRole::GitAdded);
+        let apart = bg_colors.get(BgRole::GitDeleted);
+        let fg = Mode::Dark.brand_colors();
+        let mut bufs = ScratchBuffers::default();
+        let mut bg_bg_cost = |bg_colors| {
+            let weights = Weights {
+                distance_bg_bg_weight: 1.,
+                distance_bg_fg_weight: 0.,
+                distance_fg_fg_weight: 0.,
+                ..Preset::Balanced.weights()
+            };
+            let state = State::new(bg_colors, fg.clone(), fg.clone(), weights);
+            state.distance_cost(&mut bufs, Vision::Default).value()
+        };
+        let before = bg_bg_cost(bg_colors);
+        bg_colors.set(BgRole::GitDeleted, collapsed);
+        let after = bg_bg_cost(bg_colors);
+        assert!(
+            after > before,
+            "collapsing git_deleted onto git_added didn't raise the bg ↔ bg distance cost \
+             ({before} → {after}, git_deleted was {})",
+            hex_colors(&[apart])[0]
+        );
+
+        let config = OptimizeConfig {
+            max_iterations: Some(50),
+            ..OptimizeConfig::default()
+        };
+        let mut state = State::new(bg_colors, fg.clone(), fg, Preset::Balanced.weights());
+        let report = state.optimize(&mut Rng::seed_from_u64(0), &config);
+        let [added, deleted] = [BgRole::GitAdded, BgRole::GitDeleted]
+            .map(|role| report.final_state.bg_colors.get(role));
+        let d = distance(added, deleted, report.final_state.space);
+        assert!(
+            d >= DeltaE::PERCEPTIBLE,
+            "git backgrounds stayed together: {:?} only {d:.1} apart",
+            hex_colors(&[added, deleted])
+        );
+    }
+
+    /// Checks [`palette_hash`] against a recorded value, so that a change to
+    /// the hash or the hex formatting can't silently invalidate every cache
+    /// keyed on it, and that moving a color by less than one 8-bit step
+    /// leaves it alone.
+    #[test]
+    fn palette_hash_matches_the_recorded_value() {
+        const GOLDEN: &str = "0ea4247c1a8e7c67";
+        let bg = colors_from_hex(&["#1d212f", "#343a4d"]).unwrap();
+        let mut fg = colors_from_hex(&["#ffc247", "#00a1c7", "#820dde"]).unwrap();
+        assert_eq!(palette_hash(&bg, &fg), GOLDEN, "palette hash changed");
+        fg[0].red -= 0.1 / 255.;
+        assert_eq!(
+            palette_hash(&bg, &fg),
+            GOLDEN,
+            "palette hash depends on more than the hex codes"
+        );
+    }
+
+    /// Runs `mode` through the same entry point as a normal run, minus the
+    /// report, for the seed "regression" and a few iterations. Greedy
+    /// acceptance only takes improving moves, so even a run cut off this
+    /// early ends below its start.
+    fn pinned_run(mode: Mode) -> Report {
+        let opts = Options {
+            seed: Some("regression".to_string()),
+            verbosity: Verbosity::Quiet,
+            max_iterations: Some(30),
+            acceptance_rule: AcceptanceRule::Greedy,
+            ..Options::default()
+        };
+        mode_main(mode, &opts, &mut Output::discard(), TableStream::Stdout)
+            .expect("Built-in options are valid")
+    }
+
+    /// The final total cost and exact final palettes (active backgrounds,
+    /// then foregrounds) of [`pinned_run`]. Any change to the optimizer,
+    /// cost or RNG wiring that moves a color by one hex step shows up here,
+    /// even within the 1% cost tolerance; update these when that's intended.
+    #[test]
+    fn pinned_run_matches_the_recorded_costs_and_palettes() {
+        #[rustfmt::skip]
+        let expected = [
+            (
+                Mode::Dark,
+                270.97,
+                ["#1d212f", "#013852", "#8c6522", "#0e3500", "#00002f", "#250900"],
+                [
+                    "#ffffb8", "#ffc9c6", "#ffd5f5", "#eacbff", "#b8bfff", "#beffff", "#d4ffda",
+                    "#ffdb56", "#ff531c", "#ee2c84", "#a100f5", "#3247ff", "#00d0f9", "#8bf5be",
+                    "#ffc548", "#ff1916", "#b81270", "#820dbc", "#502ddd", "#00b2ef", "#16a850",
+                    "#423276", "#a7b2d1",
+                ],
+            ),
+            (
+                Mode::Light,
+                276.71,
+                ["#ffffff", "#d3ffff", "#ffdfb5", "#fbffb6", "#d5f6ff", "#ffffc0"],
+                [
+                    "#ffda59", "#ff5029", "#a4187f", "#9100fa", "#584af7", "#00cef2", "#7bfdd0",
+                    "#ffc748", "#f22300", "#bd0058", "#8200c9", "#3f27f6", "#00ace3", "#14ac4f",
+                    "#ff9d19", "#a80012", "#9e1060", "#611686", "#3826c5", "#00406d", "#006b30",
+                    "#3d324b", "#c0b9eb",
+                ],
+            ),
+        ];
+        for (mode, cost, bg, fg) in expected {
+            let report = pinned_run(mode);
+            let start = report.start_cost.total(&report.weights);
+            let end = report.final_cost.total(&report.weights);
+            assert!(end < start, "{} mode cost did not improve", mode.text());
+            assert!(
+                (end - cost).abs() <= 0.01 * cost,
+                "{} mode final cost {end} is not within 1% of {cost}",
+                mode.text()
+            );
+            assert_eq!(
+                hex_colors(&report.final_state.bg_colors.into_array()),
+                bg,
+                "{} mode background colors changed",
+                mode.text()
+            );
+            assert_eq!(
+                hex_colors(&report.final_state.fg_colors),
+                fg,
+                "{} mode foreground colors changed",
+                mode.text()
+            );
+        }
+    }
 }
@@ -1,14 +1,142 @@
-pub fn root_mean_square_distance(x: f32, s: &[f32]) -> f32 {
+use std::{cmp::Ordering, str::FromStr};
+
+use crate::error::{Error, Result};
+
+/// The float type the root mean square helpers sum in. Colors and the
+/// values summed stay `f32` either way; `f64` only keeps sums over many
+/// pairs from rounding away tiny cost differences.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Precision {
+    #[default]
+    F32,
+    F64,
+}
+
+impl Precision {
+    pub fn text(self) -> &'static str {
+        match self {
+            Precision::F32 => "f32",
+            Precision::F64 => "f64",
+        }
+    }
+}
+
+impl FromStr for Precision {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "f32" => Ok(Precision::F32),
+            "f64" => Ok(Precision::F64),
+            _ => Err(Error::unknown("precision", s)),
+        }
+    }
+}
+
+// Empty inputs get a defined value rather than NaN or a panic: an empty set
+// of distances contributes no cost.
+
+pub fn root_mean_square_distance(x: f32, s: &[f32], precision: Precision) -> f32 {
+    if s.is_empty() {
+        return 0.;
+    }
+    if precision == Precision::F64 {
+        return weighted_root_mean_square_distance_f64(x, s, std::iter::repeat(1.));
+    }
     f32::sqrt(s.iter().map(|y| (x - y) * (x - y)).sum::<f32>() / (s.len() as f32))
 }
 
-pub fn root_mean_square(s: &[f32]) -> f32 {
+pub fn root_mean_square(s: &[f32], precision: Precision) -> f32 {
+    if s.is_empty() {
+        return 0.;
+    }
+    if precision == Precision::F64 {
+        return weighted_root_mean_square_distance_f64(0., s, std::iter::repeat(1.));
+    }
     // Don't need to worry about infinity because numbers will be small
     f32::sqrt(s.iter().map(|x| x * x).sum::<f32>() / (s.len() as f32))
 }
 
+/// Like [`root_mean_square_distance`], with the contribution of each value
+/// scaled by the matching entry of `w`. Equal weights give the unweighted
+/// result.
+pub fn weighted_root_mean_square_distance(
+    x: f32,
+    s: &[f32],
+    w: impl Iterator<Item = f32>,
+    precision: Precision,
+) -> f32 {
+    if precision == Precision::F64 {
+        return weighted_root_mean_square_distance_f64(x, s, w);
+    }
+    let (mut sum, mut total_weight) = (0., 0.);
+    for (y, w) in s.iter().zip(w) {
+        sum += w * (x - y) * (x - y);
+        total_weight += w;
+    }
+    if total_weight == 0. {
+        return 0.;
+    }
+    f32::sqrt(sum / total_weight)
+}
+
+/// [`weighted_root_mean_square_distance`] with the sums in `f64`.
+fn weighted_root_mean_square_distance_f64(x: f32, s: &[f32], w: impl Iterator<Item = f32>) -> f32 {
+    let (mut sum, mut total_weight) = (0f64, 0f64);
+    for (y, w) in s.iter().zip(w) {
+        let (d, w) = (f64::from(x - y), f64::from(w));
+        sum += w * d * d;
+        total_weight += w;
+    }
+    if total_weight == 0. {
+        return 0.;
+    }
+    f64::sqrt(sum / total_weight) as f32
+}
+
+/// Like [`root_mean_square`], with the contribution of each value scaled by
+/// the matching entry of `w`.
+pub fn weighted_root_mean_square(
+    s: &[f32],
+    w: impl Iterator<Item = f32>,
+    precision: Precision,
+) -> f32 {
+    weighted_root_mean_square_distance(0., s, w, precision)
+}
+
+/// The largest weighted shortfall `w * (x - y)` of any value below `x`,
+/// divided by the largest weight so that it stays in `[0, x]` whatever the
+/// weights; the worst-case counterpart of
+/// [`weighted_root_mean_square_distance`]. Equal to `x - min(s)` for equal
+/// weights (but never negative), and 0 if every weight is.
+pub fn weighted_max_shortfall(x: f32, s: &[f32], w: impl Iterator<Item = f32>) -> f32 {
+    let (mut worst, mut max_weight) = (0f32, 0f32);
+    for (y, w) in s.iter().zip(w) {
+        worst = worst.max(w * (x - y));
+        max_weight = max_weight.max(w);
+    }
+    if max_weight == 0. {
+        return 0.;
+    }
+    worst / max_weight
+}
+
+/// Orders like [`f32::total_cmp`], except that every NaN sorts after all
+/// numbers regardless of its sign bit, and NaNs are equal to each other.
+/// For sorting scores where a NaN should stand out rather than panic.
+pub fn cmp_nan_last(a: f32, b: f32) -> Ordering {
+    match (a.is_nan(), b.is_nan()) {
+        (false, false) => a.total_cmp(&b),
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Greater,
+        (false, true) => Ordering::Less,
+    }
+}
+
 pub fn max_minus_min(s: &[f32]) -> f32 {
-    assert!(s.len() > 0);
+    if s.is_empty() {
+        return 0.;
+    }
     let mut max: f32 = f32::NEG_INFINITY;
     let mut min: f32 = f32::INFINITY;
     for x in s.iter() {
@@ -17,3 +145,100 @@ pub fn max_minus_min(s: &[f32]) -> f32 {
     }
     max - min
 }
+
+/// Summary of a set of values, as computed by [`distance_stats`].
+#[derive(Copy, Clone, Debug)]
+pub struct Stats {
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+    /// Population standard deviation.
+    pub stddev: f32,
+}
+
+/// Computes [`Stats`] in a single pass, or `None` for an empty slice.
+pub fn distance_stats(s: &[f32]) -> Option<Stats> {
+    if s.is_empty() {
+        return None;
+    }
+    // Welford's algorithm, which doesn't lose precision when the values are
+    // large compared to their spread.
+    let mut min = f32::INFINITY;
+    let mut max = f32::NEG_INFINITY;
+    let mut mean = 0.;
+    let mut m2 = 0.;
+    for (i, x) in s.iter().enumerate() {
+        min = min.min(*x);
+        max = max.max(*x);
+        let delta = x - mean;
+        mean += delta / (i + 1) as f32;
+        m2 += delta * (x - mean);
+    }
+    Some(Stats {
+        min,
+        max,
+        mean,
+        stddev: f32::sqrt(m2 / s.len() as f32),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nans_sort_last_whatever_their_sign() {
+        let mut values = [f32::NAN, 1., -f32::NAN, f32::NEG_INFINITY, 0.];
+        values.sort_by(|a, b| cmp_nan_last(*a, *b));
+        assert_eq!(values[..3], [f32::NEG_INFINITY, 0., 1.]);
+        assert!(values[3..].iter().all(|x| x.is_nan()));
+        assert_eq!(cmp_nan_last(f32::NAN, -f32::NAN), Ordering::Equal);
+        assert_eq!(cmp_nan_last(-f32::NAN, f32::INFINITY), Ordering::Greater);
+    }
+
+    #[test]
+    fn distance_stats_of_a_known_slice() {
+        assert!(distance_stats(&[]).is_none());
+        let stats = distance_stats(&[2., 4., 4., 4., 5., 5., 7., 9.]).unwrap();
+        assert_eq!((stats.min, stats.max), (2., 9.));
+        assert!((stats.mean - 5.).abs() < 1e-6);
+        assert!((stats.stddev - 2.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn max_shortfall_stays_within_the_ideal() {
+        let s = [90., 40., 70.];
+        assert_eq!(
+            weighted_max_shortfall(100., &s, [1., 1., 1.].into_iter()),
+            60.
+        );
+        // Only the relative weights matter, and large ones can't push the
+        // shortfall past the ideal.
+        let heavy = weighted_max_shortfall(100., &s, [16., 4., 4.].into_iter());
+        assert_eq!(heavy, 4. * 60. / 16.);
+        let scaled = weighted_max_shortfall(100., &[0., 50.], [8., 4.].into_iter());
+        assert_eq!(scaled, 100.);
+        assert_eq!(
+            weighted_max_shortfall(100., &s, [0., 0., 0.].into_iter()),
+            0.
+        );
+    }
+
+    #[test]
+    fn empty_and_weightless_inputs_cost_nothing() {
+        assert_eq!(max_minus_min(&[]), 0.);
+        for precision in [Precision::F32, Precision::F64] {
+            assert_eq!(root_mean_square(&[], precision), 0.);
+            assert_eq!(root_mean_square_distance(100., &[], precision), 0.);
+            let zero_weights = [0., 0.].into_iter();
+            assert_eq!(
+                weighted_root_mean_square_distance(100., &[1., 2.], zero_weights, precision),
+                0.
+            );
+            assert_eq!(
+                weighted_root_mean_square(&[], std::iter::empty(), precision),
+                0.
+            );
+        }
+    }
+}
@@ -0,0 +1,335 @@
+//! OKHSL, a hue/saturation/lightness space built on OKLab.
+//!
+//! Port of Björn Ottosson's reference implementation:
+//! https://bottosson.github.io/posts/colorpicker/
+//!
+//! Computes in f64: near the blue corner of the gamut f32 loses enough
+//! precision that colors don't survive a round trip.
+
+// The coefficients are kept as published.
+#![allow(clippy::excessive_precision)]
+
+use std::f64::consts::PI;
+
+use crate::{
+    color::{Color, LinearRgb},
+    convert::triple_to_array,
+};
+
+/// All components are in 0..=1; `h` is in turns.
+#[derive(Copy, Clone, Debug)]
+pub struct Okhsl {
+    pub h: f32,
+    pub s: f32,
+    pub l: f32,
+}
+
+struct Lab {
+    l: f64,
+    a: f64,
+    b: f64,
+}
+
+fn linear_srgb_to_oklab([r, g, b]: [f64; 3]) -> Lab {
+    let l = 0.41222147 * r + 0.53633254 * g + 0.05144599 * b;
+    let m = 0.21190350 * r + 0.68069955 * g + 0.10739696 * b;
+    let s = 0.08830246 * r + 0.28171884 * g + 0.62997870 * b;
+
+    let (l_, m_, s_) = (l.cbrt(), m.cbrt(), s.cbrt());
+    Lab {
+        l: 0.21045426 * l_ + 0.79361779 * m_ - 0.00407205 * s_,
+        a: 1.97799850 * l_ - 2.42859221 * m_ + 0.45059371 * s_,
+        b: 0.02590404 * l_ + 0.78277177 * m_ - 0.80867577 * s_,
+    }
+}
+
+fn oklab_to_linear_srgb(c: Lab) -> [f64; 3] {
+    let l_ = c.l + 0.39633778 * c.a + 0.21580376 * c.b;
+    let m_ = c.l - 0.10556135 * c.a - 0.06385417 * c.b;
+    let s_ = c.l - 0.08948418 * c.a - 1.29148555 * c.b;
+
+    let (l, m, s) = (l_ * l_ * l_, m_ * m_ * m_, s_ * s_ * s_);
+    [
+        4.07674166 * l - 3.30771159 * m + 0.23096993 * s,
+        -1.26843800 * l + 2.60975740 * m - 0.34131940 * s,
+        -0.00419609 * l - 0.70341861 * m + 1.70761470 * s,
+    ]
+}
+
+// Finds the maximum saturation (C/L) that fits in sRGB for the hue given by
+// the unit vector (a, b).
+fn compute_max_saturation(a: f64, b: f64) -> f64 {
+    // Max saturation is reached when one of r, g or b goes below zero; the
+    // coefficients depend on which one does so first.
+    let (k0, k1, k2, k3, k4, wl, wm, ws) = if -1.88170328 * a - 0.80936493 * b > 1. {
+        // Red
+        let w = (4.07674166, -3.30771159, 0.23096993);
+        (
+            1.19086277, 1.76576728, 0.59662641, 0.75515197, 0.56771245, w.0, w.1, w.2,
+        )
+    } else if 1.81444104 * a - 1.19445276 * b > 1. {
+        // Green
+        let w = (-1.26843800, 2.60975740, -0.34131940);
+        (
+            0.73956515,
+            -0.45954404,
+            0.08285427,
+            0.12541070,
+            0.14503204,
+            w.0,
+            w.1,
+            w.2,
+        )
+    } else {
+        // Blue
+        let w = (-0.00419609, -0.70341861, 1.70761470);
+        (
+            1.35733652,
+            -0.00915799,
+            -1.15130210,
+            -0.50559606,
+            0.00692167,
+            w.0,
+            w.1,
+            w.2,
+        )
+    };
+
+    // Polynomial approximation, then one step of Halley's method.
+    let s = k0 + k1 * a + k2 * b + k3 * a * a + k4 * a * b;
+
+    let k_l = 0.39633778 * a + 0.21580376 * b;
+    let k_m = -0.10556135 * a - 0.06385417 * b;
+    let k_s = -0.08948418 * a - 1.29148555 * b;
+
+    let l_ = 1. + s * k_l;
+    let m_ = 1. + s * k_m;
+    let s_ = 1. + s * k_s;
+
+    let (l, m, s3) = (l_ * l_ * l_, m_ * m_ * m_, s_ * s_ * s_);
+    let (l_ds, m_ds, s_ds) = (3. * k_l * l_ * l_, 3. * k_m * m_ * m_, 3. * k_s * s_ * s_);
+    let (l_ds2, m_ds2, s_ds2) = (
+        6. * k_l * k_l * l_,
+        6. * k_m * k_m * m_,
+        6. * k_s * k_s * s_,
+    );
+
+    let f = wl * l + wm * m + ws * s3;
+    let f1 = wl * l_ds + wm * m_ds + ws * s_ds;
+    let f2 = wl * l_ds2 + wm * m_ds2 + ws * s_ds2;
+
+    s - f * f1 / (f1 * f1 - 0.5 * f * f2)
+}
+
+/// Lightness and chroma of the most saturated in-gamut color of a hue.
+struct Cusp {
+    l: f64,
+    c: f64,
+}
+
+fn find_cusp(a: f64, b: f64) -> Cusp {
+    let s_cusp = compute_max_saturation(a, b);
+    let rgb_at_max = oklab_to_linear_srgb(Lab {
+        l: 1.,
+        a: s_cusp * a,
+        b: s_cusp * b,
+    });
+    let l_cusp = (1. / rgb_at_max[0].max(rgb_at_max[1]).max(rgb_at_max[2])).cbrt();
+    Cusp {
+        l: l_cusp,
+        c: l_cusp * s_cusp,
+    }
+}
+
+// Finds t where the line L = l0 * (1 - t) + t * l1, C = t * c1 leaves the
+// sRGB gamut.
+fn find_gamut_intersection(a: f64, b: f64, l1: f64, c1: f64, l0: f64, cusp: &Cusp) -> f64 {
+    if (l1 - l0) * cusp.c - (cusp.l - l0) * c1 <= 0. {
+        // Lower half
+        return cusp.c * l0 / (c1 * cusp.l + cusp.c * (l0 - l1));
+    }
+
+    // Upper half: intersect with the triangle, then one Halley step.
+    let mut t = cusp.c * (l0 - 1.) / (c1 * (cusp.l - 1.) + cusp.c * (l0 - l1));
+
+    let d_l = l1 - l0;
+    let d_c = c1;
+
+    let k_l = 0.39633778 * a + 0.21580376 * b;
+    let k_m = -0.10556135 * a - 0.06385417 * b;
+    let k_s = -0.08948418 * a - 1.29148555 * b;
+
+    let l_dt = d_l + d_c * k_l;
+    let m_dt = d_l + d_c * k_m;
+    let s_dt = d_l + d_c * k_s;
+
+    let big_l = l0 * (1. - t) + t * l1;
+    let big_c = t * c1;
+
+    let l_ = big_l + big_c * k_l;
+    let m_ = big_l + big_c * k_m;
+    let s_ = big_l + big_c * k_s;
+
+    let (l, m, s) = (l_ * l_ * l_, m_ * m_ * m_, s_ * s_ * s_);
+    let (ldt, mdt, sdt) = (
+        3. * l_dt * l_ * l_,
+        3. * m_dt * m_ * m_,
+        3. * s_dt * s_ * s_,
+    );
+    let (ldt2, mdt2, sdt2) = (
+        6. * l_dt * l_dt * l_,
+        6. * m_dt * m_dt * m_,
+        6. * s_dt * s_dt * s_,
+    );
+
+    let step = |w: (f64, f64, f64)| {
+        let f = w.0 * l + w.1 * m + w.2 * s - 1.;
+        let f1 = w.0 * ldt + w.1 * mdt + w.2 * sdt;
+        let f2 = w.0 * ldt2 + w.1 * mdt2 + w.2 * sdt2;
+        let u = f1 / (f1 * f1 - 0.5 * f * f2);
+        if u >= 0. {
+            -f * u
+        } else {
+            f64::MAX
+        }
+    };
+    let t_r = step((4.07674166, -3.30771159, 0.23096993));
+    let t_g = step((-1.26843800, 2.60975740, -0.34131940));
+    let t_b = step((-0.00419609, -0.70341861, 1.70761470));
+
+    t += t_r.min(t_g).min(t_b);
+    t
+}
+
+const K_1: f64 = 0.206;
+const K_2: f64 = 0.03;
+const K_3: f64 = (1. + K_1) / (1. + K_2);
+
+// Maps OKLab lightness to a closer match of CIELAB's L*.
+fn toe(x: f64) -> f64 {
+    0.5 * (K_3 * x - K_1 + ((K_3 * x - K_1) * (K_3 * x - K_1) + 4. * K_2 * K_3 * x).sqrt())
+}
+
+fn toe_inv(x: f64) -> f64 {
+    (x * x + K_1 * x) / (K_3 * (x + K_2))
+}
+
+// Smooth approximation of the cusp location, as (S, T) slopes.
+fn get_st_mid(a: f64, b: f64) -> (f64, f64) {
+    let s = 0.11516993
+        + 1. / (7.44778970
+            + 4.15901240 * b
+            + a * (-2.19557347
+                + 1.75198401 * b
+                + a * (-2.13704948 - 10.02301043 * b
+                    + a * (-4.24894561 + 5.38770819 * b + 4.69891013 * a))));
+    let t = 0.11239642
+        + 1. / (1.61320320 - 0.68124379 * b
+            + a * (0.40370612
+                + 0.90148123 * b
+                + a * (-0.27087943
+                    + 0.61223990 * b
+                    + a * (0.00299215 - 0.45399568 * b - 0.14661872 * a))));
+    (s, t)
+}
+
+/// Chroma at saturation 0.8 (`mid`) and 1 (`max`), and a hue-independent
+/// reference chroma `zero` used to shape low saturations.
+struct Chromas {
+    zero: f64,
+    mid: f64,
+    max: f64,
+}
+
+fn get_cs(l: f64, a: f64, b: f64) -> Chromas {
+    let cusp = find_cusp(a, b);
+    let max = find_gamut_intersection(a, b, l, 1., l, &cusp);
+    let (s_max, t_max) = (cusp.c / cusp.l, cusp.c / (1. - cusp.l));
+
+    // Compensates for the curved part of the gamut shape.
+    let k = max / (l * s_max).min((1. - l) * t_max);
+
+    let (s_mid, t_mid) = get_st_mid(a, b);
+    let c_a = l * s_mid;
+    let c_b = (1. - l) * t_mid;
+    let mid = 0.9 * k * (1. / (1. / c_a.powi(4) + 1. / c_b.powi(4))).sqrt().sqrt();
+
+    let c_a = l * 0.4;
+    let c_b = (1. - l) * 0.8;
+    let zero = (1. / (1. / (c_a * c_a) + 1. / (c_b * c_b))).sqrt();
+
+    Chromas { zero, mid, max }
+}
+
+const MID: f64 = 0.8;
+const MID_INV: f64 = 1.25;
+
+impl Okhsl {
+    pub fn from_srgb(c: Color) -> Okhsl {
+        let lab =
+            linear_srgb_to_oklab(triple_to_array(c.into_linear().into_components()).map(f64::from));
+        let l = toe(lab.l) as f32;
+        let chroma = (lab.a * lab.a + lab.b * lab.b).sqrt();
+        // Grays (and black/white) have no hue; report them as unsaturated red.
+        if chroma < 1e-6 || lab.l <= 0. || lab.l >= 1. {
+            return Okhsl { h: 0., s: 0., l };
+        }
+        let (a, b) = (lab.a / chroma, lab.b / chroma);
+        let h = 0.5 + 0.5 * (-lab.b).atan2(-lab.a) / PI;
+
+        let cs = get_cs(lab.l, a, b);
+        let s = if chroma < cs.mid {
+            let k_1 = MID * cs.zero;
+            let k_2 = 1. - k_1 / cs.mid;
+            let t = chroma / (k_1 + k_2 * chroma);
+            t * MID
+        } else {
+            let k_0 = cs.mid;
+            let k_1 = (1. - MID) * cs.mid * cs.mid * MID_INV * MID_INV / cs.zero;
+            let k_2 = 1. - k_1 / (cs.max - cs.mid);
+            let t = (chroma - k_0) / (k_1 + k_2 * (chroma - k_0));
+            MID + (1. - MID) * t
+        };
+        Okhsl {
+            h: h as f32,
+            s: s as f32,
+            l,
+        }
+    }
+
+    /// Converts back to sRGB. The result may fall slightly outside the
+    /// gamut, so callers should clamp.
+    pub fn to_srgb(self) -> Color {
+        if self.l >= 1. {
+            return Color::new(1., 1., 1.);
+        }
+        if self.l <= 0. {
+            return Color::new(0., 0., 0.);
+        }
+        let (h, s) = (self.h as f64, self.s as f64);
+        let a = (2. * PI * h).cos();
+        let b = (2. * PI * h).sin();
+        let l = toe_inv(self.l as f64);
+
+        let cs = get_cs(l, a, b);
+        let chroma = if s < MID {
+            let t = MID_INV * s;
+            let k_1 = MID * cs.zero;
+            let k_2 = 1. - k_1 / cs.mid;
+            t * k_1 / (1. - k_2 * t)
+        } else {
+            let t = (s - MID) / (1. - MID);
+            let k_0 = cs.mid;
+            let k_1 = (1. - MID) * cs.mid * cs.mid * MID_INV * MID_INV / cs.zero;
+            let k_2 = 1. - k_1 / (cs.max - cs.mid);
+            k_0 + t * k_1 / (1. - k_2 * t)
+        };
+
+        let rgb = oklab_to_linear_srgb(Lab {
+            l,
+            a: chroma * a,
+            b: chroma * b,
+        });
+        Color::from_linear(LinearRgb::new(rgb[0] as f32, rgb[1] as f32, rgb[2] as f32))
+    }
+}
@@ -0,0 +1,40 @@
+//! Palette previews drawn with 24-bit ANSI escapes, for terminals that
+//! support truecolor.
+
+use std::fmt::Write;
+
+use crate::color::Color;
+
+/// Escape code parameters `R;G;B` for `c`.
+fn ansi_rgb(c: Color) -> String {
+    let c = c.into_format::<u8>();
+    format!("{};{};{}", c.red, c.green, c.blue)
+}
+
+/// `text` drawn in `fg` on `bg`.
+fn paint(text: &str, fg: Color, bg: Color) -> String {
+    format!(
+        "\x1b[38;2;{}m\x1b[48;2;{}m{text}\x1b[0m",
+        ansi_rgb(fg),
+        ansi_rgb(bg)
+    )
+}
+
+/// A row of foreground swatches, then one row per background with each
+/// foreground's index drawn on it. Columns line up with the swatches.
+pub fn palette_preview(bg_colors: &[Color], fg_colors: &[Color]) -> String {
+    let mut out = String::new();
+    write!(out, "{:>8} ", "").unwrap();
+    for fg in fg_colors {
+        write!(out, "{}", paint("    ", *fg, *fg)).unwrap();
+    }
+    writeln!(out).unwrap();
+    for (j, bg) in bg_colors.iter().enumerate() {
+        write!(out, "{:>8} ", format!("bg {j}")).unwrap();
+        for (i, fg) in fg_colors.iter().enumerate() {
+            write!(out, "{}", paint(&format!("{i:^4}"), *fg, *bg)).unwrap();
+        }
+        writeln!(out).unwrap();
+    }
+    out
+}
@@ -1 +1,25 @@
+use crate::error::{Error, Result};
+
+/// The RNG the binary seeds and runs with. Code that only draws numbers
+/// takes any `rand::Rng` instead, so a counting or scripted RNG can stand
+/// in for it; restarts need this type for its independent streams.
 pub type Rng = rand_chacha::ChaCha8Rng;
+
+/// The full RNG seed; printing it allows a run to be reproduced exactly.
+pub type Seed = [u8; 32];
+
+pub fn seed_to_hex(seed: &Seed) -> String {
+    seed.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+pub fn seed_from_hex(s: &str) -> Result<Seed> {
+    let err = || Error::Usage(format!("Expected a seed of 64 hex digits, got {s}"));
+    if s.len() != 64 || !s.is_ascii() {
+        return Err(err());
+    }
+    let mut seed = [0u8; 32];
+    for (i, b) in seed.iter_mut().enumerate() {
+        *b = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).map_err(|_| err())?;
+    }
+    Ok(seed)
+}
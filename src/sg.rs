@@ -1,9 +1,10 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, str::FromStr};
 
 use crate::{
     color::*,
-    cost::{ContrastNeed, ScaledCost},
-    math::root_mean_square,
+    cost::{ContrastLevel, ContrastNeed, ContrastSigmoid, ScaledCost},
+    error::Error,
+    math::{root_mean_square, Precision},
 };
 
 #[derive(Copy, Clone)]
@@ -44,7 +45,7 @@ impl Mode {
         }
         // HACK: Just add the grey_scale colors here for now
         out.extend(self.grey_scale_colors().iter());
-        return out;
+        out
     }
     pub fn text(&self) -> &'static str {
         match self {
@@ -67,58 +68,152 @@ pub struct BackgroundColors {
     git_deleted: Color,
 }
 
+/// Names one of the slots in [`BackgroundColors`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BgRole {
+    Main,
+    RangeSelection,
+    LineSelection,
+    GitAdded,
+    GitLineSelection,
+    GitDeleted,
+}
+
+impl BgRole {
+    pub const ALL: [BgRole; 6] = [
+        BgRole::Main,
+        BgRole::RangeSelection,
+        BgRole::LineSelection,
+        BgRole::GitAdded,
+        BgRole::GitLineSelection,
+        BgRole::GitDeleted,
+    ];
+
+    pub fn text(self) -> &'static str {
+        match self {
+            BgRole::Main => "main",
+            BgRole::RangeSelection => "range_selection",
+            BgRole::LineSelection => "line_selection",
+            BgRole::GitAdded => "git_added",
+            BgRole::GitLineSelection => "git_line_selection",
+            BgRole::GitDeleted => "git_deleted",
+        }
+    }
+}
+
+impl FromStr for BgRole {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        BgRole::ALL
+            .into_iter()
+            .find(|role| role.text() == s)
+            .ok_or_else(|| Error::unknown("background role", s))
+    }
+}
+
 impl BackgroundColors {
-    pub const COUNT: usize = 2;
-    pub fn into_array(&self) -> [Color; Self::COUNT] {
-        [
-            self.main,
-            // self.range_selection,
-            self.line_selection,
-            // self.git_added,
-            // self.git_line_selection,
-            // self.git_deleted,
-        ]
+    pub fn get(&self, role: BgRole) -> Color {
+        match role {
+            BgRole::Main => self.main,
+            BgRole::RangeSelection => self.range_selection,
+            BgRole::LineSelection => self.line_selection,
+            BgRole::GitAdded => self.git_added,
+            BgRole::GitLineSelection => self.git_line_selection,
+            BgRole::GitDeleted => self.git_deleted,
+        }
+    }
+
+    pub fn set(&mut self, role: BgRole, c: Color) {
+        *self.get_mut(role) = c;
+    }
+
+    fn get_mut(&mut self, role: BgRole) -> &mut Color {
+        match role {
+            BgRole::Main => &mut self.main,
+            BgRole::RangeSelection => &mut self.range_selection,
+            BgRole::LineSelection => &mut self.line_selection,
+            BgRole::GitAdded => &mut self.git_added,
+            BgRole::GitLineSelection => &mut self.git_line_selection,
+            BgRole::GitDeleted => &mut self.git_deleted,
+        }
     }
 
-    pub const MODIFIABLE_COUNT: usize = 1;
+    /// The editor canvas. It's fixed by the brand, so the optimizer never
+    /// moves it, but it stays in every contrast and distance pair as the
+    /// reference the other backgrounds are measured against.
+    pub const FIXED: BgRole = BgRole::Main;
+
+    /// The backgrounds the optimizer may tune, in slot order: every role
+    /// but the fixed one, so that the selection and git diff backgrounds
+    /// stay distinguishable from each other as well as from the canvas.
+    pub const MODIFIABLE: [BgRole; Self::MODIFIABLE_COUNT] = [
+        BgRole::LineSelection,
+        BgRole::RangeSelection,
+        BgRole::GitAdded,
+        BgRole::GitLineSelection,
+        BgRole::GitDeleted,
+    ];
+    pub const MODIFIABLE_COUNT: usize = 5;
+
+    /// The roles that [`BackgroundColors::into_array`] returns: the fixed
+    /// one first, then the modifiable ones.
+    pub const ACTIVE: [BgRole; Self::COUNT] = {
+        let mut roles = [Self::FIXED; Self::COUNT];
+        let mut i = 0;
+        while i < Self::MODIFIABLE_COUNT {
+            roles[i + 1] = Self::MODIFIABLE[i];
+            i += 1;
+        }
+        roles
+    };
+    pub const COUNT: usize = 1 + Self::MODIFIABLE_COUNT;
+
+    pub fn into_array(self) -> [Color; Self::COUNT] {
+        Self::ACTIVE.map(|role| self.get(role))
+    }
+
+    /// Replaces the colors that [`BackgroundColors::into_array`] returns.
+    pub fn set_active(&mut self, active: [Color; Self::COUNT]) {
+        for (role, c) in Self::ACTIVE.into_iter().zip(active) {
+            *self.get_mut(role) = c;
+        }
+    }
 
     pub fn updateable_array(&self) -> [Color; Self::MODIFIABLE_COUNT] {
-        [
-            self.line_selection,
-            // self.git_added,
-            // self.git_line_selection,
-            // self.git_deleted,
-        ]
+        Self::MODIFIABLE.map(|role| self.get(role))
     }
 
     pub fn update(&mut self, new: [Color; Self::MODIFIABLE_COUNT]) {
-        self.line_selection = new[0];
-        // self.git_added = new[1];
-        // self.git_line_selection = new[2];
-        // self.git_deleted = new[3];
-    }
-
-    pub fn contrast_cost(&self) -> ScaledCost {
-        let pairs = vec![
-            (self.main, self.range_selection),
-            (self.main, self.line_selection),
-            (self.main, self.git_added),
-            (self.main, self.git_line_selection),
-            (self.main, self.git_deleted),
-            (self.range_selection, self.line_selection),
-            (self.range_selection, self.git_added),
-            (self.range_selection, self.git_line_selection),
-            (self.range_selection, self.git_deleted),
-            (self.git_added, self.git_line_selection),
-            (self.git_added, self.git_deleted),
-            (self.git_line_selection, self.git_deleted),
-        ];
-        let mut contrast_values = Vec::with_capacity(pairs.len());
-        for (c1, c2) in pairs.into_iter() {
-            let need = ContrastNeed::Background;
-            contrast_values.push(ContrastRatio::for_pair(c1, c2, need).cost().value());
+        for (role, c) in Self::MODIFIABLE.into_iter().zip(new) {
+            *self.get_mut(role) = c;
+        }
+    }
+
+    /// RMS contrast cost over every pair of active backgrounds, i.e. the
+    /// same colors that the distance term considers.
+    pub fn contrast_cost(
+        &self,
+        level: ContrastLevel,
+        sigmoid: ContrastSigmoid,
+        space: ColorSpace,
+        precision: Precision,
+    ) -> ScaledCost {
+        let active = self.into_array();
+        let n = active.len();
+        let n_pairs = n * n.saturating_sub(1) / 2;
+        let mut contrast_values = Vec::with_capacity(n_pairs);
+        for i in 0..n {
+            for j in (i + 1)..n {
+                let need = ContrastNeed::Background;
+                contrast_values.push(
+                    ContrastRatio::for_pair(active[i], active[j], need, level, space)
+                        .cost(sigmoid)
+                        .value(),
+                );
+            }
         }
-        ScaledCost::new(root_mean_square(&contrast_values))
+        ScaledCost::new(root_mean_square(&contrast_values, precision))
     }
 }
 
@@ -147,59 +242,89 @@ fn light_mode_bg_colors() -> BackgroundColors {
 // From
 // https://handbook.sourcegraph.com/departments/engineering/product/design/brand_guidelines/color/#secondary-colors
 //
-// These also include the primary colors.
-fn brand_colors() -> HashMap<&'static str, Vec<Color>> {
+// These also include the primary colors. Each entry is (hex, name).
+fn named_brand_colors() -> HashMap<&'static str, [(&'static str, &'static str); 7]> {
     let mut h = HashMap::new();
     h.insert(
         "mist",
         [
-            "#fff2cf", // yellow mist
-            "#ffc9c9", // orange mist
-            "#ffd1f2", // pink mist
-            "#e8d1ff", // violet mist
-            "#bfbfff", // plum mist
-            "#c7ffff", // blue mist
-            "#c4ffe8", // green mist
+            ("#fff2cf", "yellow mist"),
+            ("#ffc9c9", "orange mist"),
+            ("#ffd1f2", "pink mist"),
+            ("#e8d1ff", "violet mist"),
+            ("#bfbfff", "plum mist"),
+            ("#c7ffff", "blue mist"),
+            ("#c4ffe8", "green mist"),
         ],
     );
     h.insert(
         "light",
         [
-            "#ffdb45", // lemon
-            "#ff5543", // vermillion
-            "#d62687", // cerise
-            "#a112ff", // vivid violet
-            "#6b59ed", // plum
-            "#00cbec", // sky blue
-            "#8fedcf", // mint
+            ("#ffdb45", "lemon"),
+            ("#ff5543", "vermillion"),
+            ("#d62687", "cerise"),
+            ("#a112ff", "vivid violet"),
+            ("#6b59ed", "plum"),
+            ("#00cbec", "sky blue"),
+            ("#8fedcf", "mint"),
         ],
     );
     h.insert(
         "medium",
         [
-            "#ffc247", // orange
-            "#ed2e20", // pomegranate
-            "#c4147d", // red violet
-            "#820dde", // electric violet
-            "#5033E1", // blurple
-            "#00a1c7", // pacific blue
-            "#17ab52", // mountain meadow
+            ("#ffc247", "orange"),
+            ("#ed2e20", "pomegranate"),
+            ("#c4147d", "red violet"),
+            ("#820dde", "electric violet"),
+            ("#5033E1", "blurple"),
+            ("#00a1c7", "pacific blue"),
+            ("#17ab52", "mountain meadow"),
         ],
     );
     h.insert(
         "dark",
         [
-            "#ff9933", // carrot
-            "#c22626", // poppy
-            "#9e1769", // disco
-            "#6112a3", // seance
-            "#3826cc", // persian blue
-            "#005482", // orient
-            "#1f7d45", // eucalyptus
+            ("#ff9933", "carrot"),
+            ("#c22626", "poppy"),
+            ("#9e1769", "disco"),
+            ("#6112a3", "seance"),
+            ("#3826cc", "persian blue"),
+            ("#005482", "orient"),
+            ("#1f7d45", "eucalyptus"),
         ],
     );
-    h.into_iter()
-        .map(|(k, v)| (k, v.map(rgb).into_iter().collect()))
+    h
+}
+
+fn brand_colors() -> HashMap<&'static str, Vec<Color>> {
+    named_brand_colors()
+        .into_iter()
+        .map(|(k, v)| (k, v.map(|(hex, _)| rgb(hex)).into_iter().collect()))
+        .collect()
+}
+
+/// Name of the brand color closest to `c` (by CIEDE2000 distance).
+pub fn nearest_brand_name(c: Color, space: ColorSpace) -> &'static str {
+    let mut out = "";
+    let mut closest = f32::INFINITY;
+    for (_, group) in named_brand_colors() {
+        for (hex, name) in group {
+            let d = distance(c, rgb(hex), space);
+            if d < closest {
+                closest = d;
+                out = name;
+            }
+        }
+    }
+    out
+}
+
+/// Like [`hex_colors`], but each entry is prefixed with the nearest brand
+/// color name, e.g. "vermillion (#ff5543)".
+pub fn brand_named_colors(cs: &[Color], space: ColorSpace) -> Vec<String> {
+    cs.iter()
+        .zip(hex_colors(cs))
+        .map(|(c, hex)| format!("{} ({hex})", nearest_brand_name(*c, space)))
         .collect()
 }
 
@@ -209,3 +334,41 @@ fn brand_colors() -> HashMap<&'static str, Vec<Color>> {
 //         .into_iter()
 //         .collect()
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contrast_cost_pairs_every_active_background() {
+        let (level, sigmoid) = (ContrastLevel::default(), ContrastSigmoid::default());
+        let bg = Mode::Dark.bg_colors();
+        let (space, precision) = (ColorSpace::Srgb, Precision::default());
+        let mut pair_costs = Vec::new();
+        for (i, &a) in BackgroundColors::ACTIVE.iter().enumerate() {
+            for &b in &BackgroundColors::ACTIVE[i + 1..] {
+                let need = ContrastNeed::Background;
+                let ratio = ContrastRatio::for_pair(bg.get(a), bg.get(b), need, level, space);
+                pair_costs.push(ratio.cost(sigmoid).value());
+            }
+        }
+        let n = BackgroundColors::COUNT;
+        assert_eq!(pair_costs.len(), n * (n - 1) / 2);
+        let expected = root_mean_square(&pair_costs, precision);
+        assert_eq!(
+            bg.contrast_cost(level, sigmoid, space, precision).value(),
+            expected
+        );
+
+        // Moving any active slot onto the canvas changes the cost, so none
+        // of them is left out.
+        for role in BackgroundColors::MODIFIABLE {
+            let mut moved = bg;
+            moved.set(role, bg.get(BackgroundColors::FIXED));
+            let cost = moved
+                .contrast_cost(level, sigmoid, space, precision)
+                .value();
+            assert_ne!(cost, expected, "{}", role.text());
+        }
+    }
+}